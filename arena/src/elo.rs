@@ -0,0 +1,150 @@
+//! Turns match results into an estimate of the difference in strength between two engines.
+
+use std::fmt::{self, Display, Formatter};
+
+/// How many standard errors either side of the estimate the confidence interval covers, for 95%
+/// confidence.
+const Z_95: f64 = 1.959_964;
+
+/// The largest Elo difference reported. Winning or losing every game implies an infinite
+/// difference, which a short match can't support, so anything beyond this is shown as at least
+/// this much.
+const MAX_ELO: f64 = 1000.0;
+
+/// The results of a match, from the first engine's point of view. Amazons can't be drawn, so
+/// every game is a win or a loss.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Score {
+    pub wins: u32,
+    pub losses: u32,
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses
+    }
+
+    /// The fraction of games won, or `None` before any have been played.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.games() > 0).then(|| f64::from(self.wins) / f64::from(self.games()))
+    }
+
+    /// The Elo difference implied by the score, with a 95% confidence interval, all clamped to
+    /// [`MAX_ELO`]. `None` before any games have been played.
+    ///
+    /// The interval is the Wilson score interval on the fraction of games won, which unlike the
+    /// usual estimate plus or minus two standard errors is still sensible for a score of 0% or
+    /// 100%.
+    pub fn elo(&self) -> Option<Elo> {
+        let ratio = self.ratio()?;
+        let games = f64::from(self.games());
+        let z2 = Z_95 * Z_95;
+        let centre = (ratio + z2 / (2.0 * games)) / (1.0 + z2 / games);
+        let spread = Z_95 / (1.0 + z2 / games)
+            * (ratio * (1.0 - ratio) / games + z2 / (4.0 * games * games)).sqrt();
+        let clamped = |ratio: f64| elo_difference(ratio).clamp(-MAX_ELO, MAX_ELO);
+        Some(Elo {
+            difference: clamped(ratio),
+            lower: clamped(centre - spread),
+            upper: clamped(centre + spread),
+        })
+    }
+}
+
+/// An estimated Elo difference and the bounds of its confidence interval.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Elo {
+    pub difference: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Display for Elo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (95% CI {} to {})",
+            EloValue(self.difference),
+            EloValue(self.lower),
+            EloValue(self.upper)
+        )
+    }
+}
+
+/// Shows an Elo value that was clamped to [`MAX_ELO`] as being at least that large.
+struct EloValue(f64);
+
+impl Display for EloValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0 >= MAX_ELO {
+            f.write_str(">")?;
+        } else if self.0 <= -MAX_ELO {
+            f.write_str("<")?;
+        }
+        write!(f, "{:+.0}", self.0)
+    }
+}
+
+/// The rating difference at which a player is expected to score `ratio` of the points.
+pub fn elo_difference(ratio: f64) -> f64 {
+    400.0 * (ratio / (1.0 - ratio)).log10()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_elo_difference() {
+        assert_eq!(elo_difference(0.5), 0.0);
+        assert!((elo_difference(0.75) - 190.85).abs() < 0.01);
+        assert!((elo_difference(0.25) + 190.85).abs() < 0.01);
+        assert_eq!(elo_difference(1.0), f64::INFINITY);
+        assert_eq!(elo_difference(0.0), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(Score::default().elo(), None);
+
+        let elo = Score {
+            wins: 60,
+            losses: 40,
+        }
+        .elo()
+        .unwrap();
+        assert!((elo.difference - 70.44).abs() < 0.01);
+        assert!(elo.lower < elo.difference && elo.difference < elo.upper);
+        // The Wilson interval for 60 out of 100 is roughly 0.502 to 0.691
+        assert!((elo.lower - 1.4).abs() < 1.0, "{elo}");
+        assert!((elo.upper - 139.5).abs() < 1.0, "{elo}");
+        assert_eq!(elo.to_string(), "+70 (95% CI +1 to +139)");
+
+        let elo = Score { wins: 1, losses: 1 }.elo().unwrap();
+        assert_eq!(elo.to_string(), "+0 (95% CI -393 to +393)");
+    }
+
+    #[test]
+    fn test_clean_sweep() {
+        // Three wins out of three is only weak evidence, so the interval reaches below zero
+        let elo = Score { wins: 3, losses: 0 }.elo().unwrap();
+        assert_eq!(elo.difference, MAX_ELO);
+        assert_eq!(elo.upper, MAX_ELO);
+        assert!(elo.lower.is_finite() && elo.lower < 0.0, "{elo}");
+        assert_eq!(elo.to_string(), ">+1000 (95% CI -43 to >+1000)");
+
+        let elo = Score { wins: 0, losses: 3 }.elo().unwrap();
+        assert_eq!(elo.difference, -MAX_ELO);
+        assert_eq!(elo.lower, -MAX_ELO);
+        assert_eq!(elo.to_string(), "<-1000 (95% CI <-1000 to +43)");
+
+        // A long enough sweep rules out the engines being equal
+        let elo = Score {
+            wins: 50,
+            losses: 0,
+        }
+        .elo()
+        .unwrap();
+        assert!(elo.lower > 400.0, "{elo}");
+    }
+}
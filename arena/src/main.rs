@@ -0,0 +1,371 @@
+//! Plays two engines against each other over a match of several games, acting as referee, and
+//! reports how they did. The engines are run as child processes and spoken to with the protocol in
+//! `amazons_core::protocol`.
+
+mod elo;
+
+use amazons_core::{
+    process::EngineProcess,
+    protocol::{Command, Go, Response},
+    Board, GameRecord, Move, MoveError, Outcome,
+};
+use clap::Parser;
+use elo::Score;
+use std::{
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// How long an engine has to start up and answer `amazons` and `isready`.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The record headers naming each side's engine, indexed by side: white is 0 and black is 1.
+const SIDES: [&str; 2] = ["White", "Black"];
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// The first engine's command line, e.g. "target/release/amazons_core --eval reachable"
+    first: String,
+    /// The second engine's command line
+    second: String,
+    /// Number of games to play. The engines swap colours after every game
+    #[arg(long, default_value_t = 10)]
+    games: usize,
+    /// Time on each engine's clock at the start of a game, in milliseconds
+    #[arg(long, default_value_t = 60_000)]
+    time: u64,
+    /// Time added to an engine's clock after each of its moves, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    increment: u64,
+    /// Fixed time for every move, in milliseconds, instead of a game clock
+    #[arg(long, conflicts_with_all = ["time", "increment"])]
+    move_time: Option<u64>,
+    /// How far an engine may overrun its time before it loses, in milliseconds, to allow for the
+    /// time taken to pass messages
+    #[arg(long, default_value_t = 100)]
+    margin: u64,
+    /// The position to start every game from instead of the usual one, in the format from
+    /// `Board::to_fen`
+    #[arg(long, value_parser = Board::from_fen)]
+    fen: Option<Board>,
+    /// Append every game to this file, in the format from `amazons_core::record`
+    #[arg(long)]
+    record: Option<PathBuf>,
+}
+
+impl Args {
+    fn time_control(&self) -> String {
+        match self.move_time {
+            Some(move_time) => format!("{move_time}/move"),
+            None => format!("{}+{}", self.time, self.increment),
+        }
+    }
+
+    /// The `go` to send when the clocks are showing `clocks`.
+    fn go(&self, clocks: [Duration; 2]) -> Go {
+        match self.move_time {
+            Some(move_time) => Go {
+                movetime: Some(Duration::from_millis(move_time)),
+                ..Go::default()
+            },
+            None => Go {
+                wtime: Some(clocks[0]),
+                btime: Some(clocks[1]),
+                winc: Duration::from_millis(self.increment),
+                binc: Duration::from_millis(self.increment),
+                ..Go::default()
+            },
+        }
+    }
+}
+
+/// Why an engine lost other than by running out of moves.
+#[derive(Debug)]
+enum Forfeit {
+    Time,
+    Illegal(Move, MoveError),
+    /// The engine said it had no moves when it did.
+    Resigned,
+    /// The engine couldn't be started, exited or sent something that isn't part of the protocol.
+    Failed(String),
+}
+
+impl Display for Forfeit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Forfeit::Time => write!(f, "time forfeit"),
+            Forfeit::Illegal(mov, err) => write!(f, "illegal move {mov}: {err}"),
+            Forfeit::Resigned => write!(f, "resigned"),
+            Forfeit::Failed(reason) => write!(f, "engine failure: {reason}"),
+        }
+    }
+}
+
+/// An engine process that has been through the handshake.
+struct Engine {
+    name: String,
+    process: EngineProcess,
+}
+
+impl Engine {
+    fn start(command: &str) -> Result<Self, Forfeit> {
+        let mut words = command.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let process = EngineProcess::spawn(program, &args)
+            .map_err(|err| Forfeit::Failed(format!("couldn't start {program}: {err}")))?;
+        let mut engine = Self {
+            name: program.to_string(),
+            process,
+        };
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        engine.send(&Command::Amazons)?;
+        loop {
+            match engine.receive(deadline)? {
+                Response::Id { field, value } if field == "name" => engine.name = value,
+                Response::AmazonsOk => return Ok(engine),
+                _ => {}
+            }
+        }
+    }
+
+    /// Tells the engine a new game is starting and waits for it to be ready.
+    fn new_game(&mut self) -> Result<(), Forfeit> {
+        self.send(&Command::NewGame)?;
+        self.send(&Command::IsReady)?;
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        while self.receive(deadline)? != Response::ReadyOk {}
+        Ok(())
+    }
+
+    /// Asks for a move in the position at the end of `record`, which must arrive by `deadline`.
+    fn best_move(
+        &mut self,
+        record: &GameRecord,
+        go: Go,
+        deadline: Instant,
+    ) -> Result<Move, Forfeit> {
+        self.send(&Command::Position {
+            start: record.start.clone(),
+            moves: record.moves.clone(),
+        })?;
+        self.send(&Command::Go(go))?;
+        loop {
+            match self.receive(deadline)? {
+                Response::BestMove(Some(mov)) => return Ok(mov),
+                Response::BestMove(None) => return Err(Forfeit::Resigned),
+                _ => {}
+            }
+        }
+    }
+
+    fn send(&mut self, command: &Command) -> Result<(), Forfeit> {
+        self.process
+            .send_line(&command.to_string())
+            .map_err(|err| Forfeit::Failed(format!("couldn't send {command}: {err}")))
+    }
+
+    fn receive(&mut self, deadline: Instant) -> Result<Response, Forfeit> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let line = match self.process.read_line_timeout(timeout) {
+            Ok(Some(line)) => line,
+            Ok(None) => return Err(Forfeit::Time),
+            Err(err) => return Err(Forfeit::Failed(err.to_string())),
+        };
+        line.parse()
+            .map_err(|err| Forfeit::Failed(format!("sent {line:?}: {err}")))
+    }
+}
+
+/// Plays a game between the engines run by `commands`, white first, and returns its record with
+/// the `Result` and `Termination` headers set.
+fn play_game(commands: [&str; 2], round: usize, args: &Args) -> GameRecord {
+    let mut record = GameRecord::new(args.fen.clone().unwrap_or_default());
+    record.set_header("Round", &round.to_string());
+    for (side, command) in commands.iter().enumerate() {
+        let program = command.split_whitespace().next().unwrap_or_default();
+        record.set_header(SIDES[side], program);
+    }
+    record.set_header("TimeControl", &args.time_control());
+    let (loser, termination) = match referee(commands, &mut record, args) {
+        Ok(loser) => (loser, "normal".to_string()),
+        Err((loser, forfeit)) => (loser, forfeit.to_string()),
+    };
+    record.set_header("Result", if loser == 0 { "0-1" } else { "1-0" });
+    record.set_header("Termination", &termination);
+    record
+}
+
+/// Plays the game out, adding the moves to `record`. Returns the side that lost, 0 for white and
+/// 1 for black, along with why if it wasn't by running out of moves.
+fn referee(
+    commands: [&str; 2],
+    record: &mut GameRecord,
+    args: &Args,
+) -> Result<usize, (usize, Forfeit)> {
+    let mut engines = vec![];
+    for (side, command) in commands.iter().enumerate() {
+        let mut engine = Engine::start(command).map_err(|forfeit| (side, forfeit))?;
+        engine.new_game().map_err(|forfeit| (side, forfeit))?;
+        record.set_header(SIDES[side], &engine.name);
+        engines.push(engine);
+    }
+    let mut board = record.start.clone();
+    let mut clocks = [Duration::from_millis(args.time); 2];
+    let margin = Duration::from_millis(args.margin);
+    loop {
+        let side = usize::from(!board.white_to_move);
+        if board.outcome() != Outcome::Ongoing {
+            return Ok(side);
+        }
+        let budget = args.move_time.map_or(clocks[side], Duration::from_millis);
+        let started = Instant::now();
+        let mov = engines[side]
+            .best_move(record, args.go(clocks), started + budget + margin)
+            .map_err(|forfeit| (side, forfeit))?;
+        if args.move_time.is_none() {
+            clocks[side] = clocks[side].saturating_sub(started.elapsed())
+                + Duration::from_millis(args.increment);
+        }
+        board
+            .try_apply_move(&mov)
+            .map_err(|err| (side, Forfeit::Illegal(mov, err)))?;
+        record.moves.push(mov);
+    }
+}
+
+/// The engines' command lines for `round`, white first, and whether the first engine is white.
+/// The first engine plays white in odd rounds.
+fn round_commands(args: &Args, round: usize) -> ([&str; 2], bool) {
+    let first_is_white = round % 2 == 1;
+    let commands = if first_is_white {
+        [args.first.as_str(), args.second.as_str()]
+    } else {
+        [args.second.as_str(), args.first.as_str()]
+    };
+    (commands, first_is_white)
+}
+
+fn main() {
+    let args = Args::parse();
+    let mut score = Score::default();
+    for round in 1..=args.games {
+        let (commands, first_is_white) = round_commands(&args, round);
+        let record = play_game(commands, round, &args);
+        let white_won = record.header("Result") == Some("1-0");
+        if white_won == first_is_white {
+            score.wins += 1;
+        } else {
+            score.losses += 1;
+        }
+        println!(
+            "Game {round}: {} vs {}, {} ({}) in {} moves. Score {} - {}",
+            record.header("White").unwrap_or("?"),
+            record.header("Black").unwrap_or("?"),
+            record.header("Result").unwrap_or("*"),
+            record.header("Termination").unwrap_or("?"),
+            record.moves.len(),
+            score.wins,
+            score.losses,
+        );
+        if let Some(path) = &args.record {
+            if let Err(err) = record.append_to(path) {
+                eprintln!("Failed to save game {round} to {}: {err}", path.display());
+            }
+        }
+    }
+
+    println!(
+        "Score of {:?} vs {:?}: {} - {}",
+        args.first, args.second, score.wins, score.losses
+    );
+    if let (Some(ratio), Some(elo)) = (score.ratio(), score.elo()) {
+        println!("Won {:.1}% of games, Elo difference {elo}", ratio * 100.0);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::process::Command as Process;
+
+    /// The path to the engine from `amazons_core`, which is built first since cargo only builds
+    /// the binaries of the package under test.
+    fn core_engine() -> String {
+        let mut build = Process::new(env!("CARGO"));
+        build.args([
+            "build",
+            "--quiet",
+            "--package",
+            "amazons_core",
+            "--bin",
+            "amazons_core",
+        ]);
+        if !cfg!(debug_assertions) {
+            build.arg("--release");
+        }
+        assert!(
+            build.status().unwrap().success(),
+            "Failed to build the engine"
+        );
+        // Tests run from target/<profile>/deps, and binaries go in target/<profile>
+        let test_exe = std::env::current_exe().unwrap();
+        let profile_dir = test_exe.parent().unwrap().parent().unwrap();
+        profile_dir.join("amazons_core").display().to_string()
+    }
+
+    #[test]
+    fn test_random_game() {
+        let engine = core_engine();
+        let first = format!("{engine} --search random --seed 1");
+        let second = format!("{engine} --search random --seed 2");
+        let args = Args::parse_from(["amazons-arena", &first, &second, "--move-time", "1000"]);
+        let (commands, first_is_white) = round_commands(&args, 1);
+        assert!(first_is_white);
+        assert_eq!(commands, [first.as_str(), second.as_str()]);
+        assert_eq!(
+            round_commands(&args, 2),
+            ([second.as_str(), first.as_str()], false)
+        );
+
+        let record = play_game(commands, 1, &args);
+        assert_eq!(record.header("Termination"), Some("normal"));
+        assert_eq!(record.header("White"), Some("amazons_core"));
+        assert_eq!(record.header("TimeControl"), Some("1000/move"));
+        // Every move is legal, the game was played to the end and the result agrees with it
+        let end = record.validate().unwrap();
+        assert_ne!(end.outcome(), Outcome::Ongoing);
+        assert_eq!(record.to_string().parse::<GameRecord>().unwrap(), record);
+
+        // With nothing on the clock, white can't reply in time
+        let args = Args::parse_from([
+            "amazons-arena",
+            &first,
+            &second,
+            "--time",
+            "0",
+            "--margin",
+            "0",
+        ]);
+        let record = play_game(commands, 1, &args);
+        assert_eq!(record.header("Result"), Some("0-1"));
+        assert_eq!(record.header("Termination"), Some("time forfeit"));
+        assert!(record.moves.is_empty());
+    }
+
+    #[test]
+    fn test_engine_failure() {
+        // `true` exits straight away, so white fails to start and loses
+        let args = Args::parse_from(["amazons-arena", "true", "true"]);
+        let record = play_game(["true", "true --flag"], 1, &args);
+        assert_eq!(record.header("Result"), Some("0-1"));
+        assert_eq!(record.header("White"), Some("true"));
+        assert!(record
+            .header("Termination")
+            .unwrap()
+            .starts_with("engine failure"));
+        assert!(record.moves.is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use amazons_core::*;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
 
 fn minimax_benchmark(c: &mut Criterion) {
     let board = Board::default();
@@ -8,16 +8,98 @@ fn minimax_benchmark(c: &mut Criterion) {
             _minimax(
                 black_box(&board),
                 black_box(2),
-                black_box(true),
-                black_box(MMT::MIN),
-                black_box(MMT::MAX),
+                black_box(-MMT::INFINITY),
+                black_box(MMT::INFINITY),
                 black_box(&mut 0),
                 black_box(None),
+                black_box(&BetterReachableHeuristic),
             )
         })
     });
 }
 
+fn make_unmake_benchmark(c: &mut Criterion) {
+    let board = Board::default();
+    // Both searches visit exactly the same nodes, so count them once and report nodes per second
+    let mut nodes = 0;
+    _minimax(
+        &board,
+        2,
+        -MMT::INFINITY,
+        MMT::INFINITY,
+        &mut nodes,
+        None,
+        &BetterReachableHeuristic,
+    );
+    let mut group = c.benchmark_group("search nodes depth 2");
+    group.throughput(Throughput::Elements(nodes as u64));
+    group.bench_function("clone", |b| {
+        b.iter(|| {
+            _minimax(
+                black_box(&board),
+                black_box(2),
+                black_box(-MMT::INFINITY),
+                black_box(MMT::INFINITY),
+                black_box(&mut 0),
+                black_box(None),
+                black_box(&BetterReachableHeuristic),
+            )
+        })
+    });
+    group.bench_function("make/unmake", |b| {
+        let mut board = board.clone();
+        let tt = TranspositionTable::new(0);
+        b.iter(|| {
+            _minimax_in_place(
+                black_box(&mut board),
+                black_box(2),
+                black_box(-MMT::INFINITY),
+                black_box(MMT::INFINITY),
+                black_box(&mut SearchContext::new(
+                    &BetterReachableHeuristic,
+                    None,
+                    None,
+                    &tt,
+                )),
+                black_box(&[]),
+                black_box(&mut vec![]),
+            )
+        })
+    });
+    group.finish();
+}
+
+fn threads_benchmark(c: &mut Criterion) {
+    // A position from the middle of a game. Depth 2 is over too quickly for the helper threads to
+    // make a difference, while depth 3 takes over a second on one thread, so the time to reach it
+    // shows how well Lazy SMP scales
+    let board: Board = "3B2B3/2x1x1x3/1x3x4/B2x5B/2x1x1x3/1x1x2x3/W1x3x2W/3x2x3/1x3x4/3W2W3 w 20"
+        .parse()
+        .unwrap();
+    let mut tt = TranspositionTable::with_size_mb(64);
+    let mut group = c.benchmark_group("minimax time to depth 3 threads");
+    group.sample_size(10);
+    for threads in [1, 2, 4, 8, 16] {
+        let limits = SearchLimits {
+            max_depth: Some(3),
+            threads,
+            ..SearchLimits::infinite()
+        };
+        group.bench_function(threads.to_string(), |b| {
+            b.iter(|| {
+                tt.clear();
+                minimax(
+                    black_box(&board),
+                    &mut tt,
+                    &limits,
+                    &BetterReachableHeuristic,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
 fn reachable_benchmark(c: &mut Criterion) {
     let board = Board::default();
     c.bench_function("reachable from one starting piece", |b| {
@@ -47,17 +129,62 @@ fn reachable_benchmark(c: &mut Criterion) {
     });
 }
 
+fn bitboard_benchmark(c: &mut Criterion) {
+    let board = Board::default();
+    let mut group = c.benchmark_group("reachable squares from all squares");
+    group.bench_function("iterator", |b| {
+        b.iter(|| {
+            for coord in 0..100 {
+                for c in board.reachable_squares(&black_box(coord)) {
+                    black_box(c);
+                }
+            }
+        })
+    });
+    group.bench_function("bitboard", |b| {
+        b.iter(|| {
+            for coord in 0..100 {
+                black_box(board.reachable_mask(&black_box(coord)));
+            }
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("white moves from start");
+    group.bench_function("iterator", |b| {
+        b.iter(|| black_box(&board).white_moves().collect::<Vec<Move>>())
+    });
+    group.bench_function("bitboard", |b| {
+        b.iter(|| {
+            let mut moves = Vec::new();
+            black_box(&board).generate_moves(0..4, &mut moves);
+            moves
+        })
+    });
+    group.finish();
+}
+
 fn heuristic_benchmark(c: &mut Criterion) {
     let board = Board::default();
     c.bench_function("heuristic empty board", |b| {
         b.iter(|| black_box(better_reachable_heuristic(black_box(&board))))
     });
+    let mut group = c.benchmark_group("evaluators empty board");
+    for evaluator in EVALUATORS {
+        group.bench_function(evaluator.name(), |b| {
+            b.iter(|| black_box(evaluator.evaluate(black_box(&board))))
+        });
+    }
+    group.finish();
 }
 
 criterion_group!(
     benches,
     minimax_benchmark,
+    make_unmake_benchmark,
+    threads_benchmark,
     reachable_benchmark,
+    bitboard_benchmark,
     heuristic_benchmark
 );
 criterion_main!(benches);
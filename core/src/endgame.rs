@@ -0,0 +1,403 @@
+//! Exact play once every amazon has been walled off from the other player's.
+//!
+//! When [`Board::is_partitioned`], neither player can get in the other's way any more, so the game
+//! is decided by who can make more moves in their own regions. A region with `n` empty squares
+//! allows at most `n` moves, since every move uses up one square, but some regions allow fewer:
+//! an amazon standing in a narrow gap may have to wall itself off from part of its territory.
+//! These defective regions are found with a search over just the owner's moves, which is far
+//! cheaper than a game tree search because there is no opponent to reply.
+
+use crate::{regions::king_dilate, Board, Move, Outcome, Owner, Region};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+/// How many positions to search between checking the clock and the stop flag.
+const CLOCK_CHECK_INTERVAL: usize = 256;
+
+/// How much searching the solver may do before it settles for bounds on the number of moves.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Budget<'a> {
+    /// Roughly how many positions to search
+    pub max_nodes: usize,
+    pub deadline: Option<Instant>,
+    /// The search gives up once this is set
+    pub stop: Option<&'a AtomicBool>,
+}
+
+impl Budget<'_> {
+    /// A budget of `max_nodes` positions, however long they take.
+    pub fn nodes(max_nodes: usize) -> Self {
+        Self {
+            max_nodes,
+            ..Self::default()
+        }
+    }
+}
+
+/// How many moves a player can make, as bounds that are equal when the solver finished.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MoveCount {
+    /// The length of the longest sequence of moves found
+    pub lower: usize,
+    /// A number of moves that certainly can't be beaten
+    pub upper: usize,
+}
+
+impl MoveCount {
+    pub fn exact(moves: usize) -> Self {
+        Self {
+            lower: moves,
+            upper: moves,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.lower == self.upper
+    }
+}
+
+impl Display for MoveCount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_exact() {
+            write!(f, "{}", self.lower)
+        } else {
+            write!(f, "{} to {}", self.lower, self.upper)
+        }
+    }
+}
+
+impl std::ops::Add for MoveCount {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            lower: self.lower + other.lower,
+            upper: self.upper + other.upper,
+        }
+    }
+}
+
+/// How many moves the owner of a region can make in it, and how to start making them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegionSolution {
+    pub moves: MoveCount,
+    /// The first move of the longest sequence found, or `None` if there are no moves
+    pub best_move: Option<Move>,
+    /// How many positions were searched
+    pub nodes: usize,
+}
+
+/// Works out how many moves the amazons in `region` can make in it, ignoring whose turn it is,
+/// searching until the `budget` runs out. `region` must be one of `board.regions()` and
+/// should be owned by one player, since the search doesn't consider the other player's moves.
+/// There is always a `best_move` if the amazons can move, however small the budget is.
+pub fn solve_region(board: &Board, region: &Region, budget: Budget) -> RegionSolution {
+    let pieces: Vec<usize> = (0..8)
+        .filter(|&idx| region.amazons.contains(&board.pieces[idx]))
+        .collect();
+    let mut solver = Solver {
+        board: board.clone(),
+        pieces,
+        nodes: 1,
+        budget,
+        next_clock_check: CLOCK_CHECK_INTERVAL,
+        aborted: false,
+        known: HashMap::new(),
+    };
+    let size = region.size();
+    let mut best = (0, None);
+    for mov in solver.ordered_moves() {
+        if best.0 == size || (best.1.is_some() && solver.out_of_nodes()) {
+            break;
+        }
+        let undo = solver.board.apply_move(&mov);
+        let moves = 1 + solver.longest(size - 1);
+        solver.board.unmake_move(undo);
+        if moves > best.0 {
+            best = (moves, Some(mov));
+        }
+    }
+    let upper = if solver.aborted && best.0 < size {
+        size
+    } else {
+        best.0
+    };
+    RegionSolution {
+        moves: MoveCount {
+            lower: best.0,
+            upper,
+        },
+        best_move: best.1,
+        nodes: solver.nodes,
+    }
+}
+
+/// A search for the longest sequence of moves the amazons in one region can make.
+struct Solver<'a> {
+    board: Board,
+    /// The indices into `board.pieces` of the amazons in the region
+    pieces: Vec<usize>,
+    nodes: usize,
+    budget: Budget<'a>,
+    // The node count at which to next check the clock
+    next_clock_check: usize,
+    /// Whether the search ran out of nodes before trying every move
+    aborted: bool,
+    /// The most moves that can be made from positions that have been searched completely
+    known: HashMap<u64, usize>,
+}
+
+impl Solver<'_> {
+    fn out_of_nodes(&mut self) -> bool {
+        if self.aborted {
+            return true;
+        }
+        if self.nodes >= self.budget.max_nodes {
+            self.aborted = true;
+        } else if self.nodes >= self.next_clock_check {
+            self.next_clock_check = self.nodes + CLOCK_CHECK_INTERVAL;
+            self.aborted = self
+                .budget
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+                || self
+                    .budget
+                    .stop
+                    .is_some_and(|stop| stop.load(Ordering::Relaxed));
+        }
+        self.aborted
+    }
+
+    /// The most moves that can be made from the current position, which has `empty` empty squares
+    /// left in the region. If the search runs out of nodes, the longest sequence found so far.
+    fn longest(&mut self, empty: usize) -> usize {
+        self.nodes += 1;
+        if empty == 0 {
+            return 0;
+        }
+        if let Some(&moves) = self.known.get(&self.board.hash) {
+            return moves;
+        }
+        let mut best = 0;
+        let mut complete = true;
+        for mov in self.ordered_moves() {
+            // Filling every square can't be beaten
+            if best == empty {
+                break;
+            }
+            if self.out_of_nodes() {
+                complete = false;
+                break;
+            }
+            let undo = self.board.apply_move(&mov);
+            best = best.max(1 + self.longest(empty - 1));
+            self.board.unmake_move(undo);
+        }
+        if complete && !self.aborted {
+            self.known.insert(self.board.hash, best);
+        }
+        best
+    }
+
+    /// The moves in the region, with the arrows fired into the tightest corners first. Filling in
+    /// dead ends before the open squares around them is usually how every square gets used.
+    fn ordered_moves(&self) -> Vec<Move> {
+        let mut moves = vec![];
+        for &idx in &self.pieces {
+            self.board.generate_piece_moves(idx, &mut moves);
+        }
+        let empty = !self.board.collisions;
+        moves.sort_by_key(|&Move(_, _, arrow)| (king_dilate(1 << arrow) & empty).count_ones());
+        moves
+    }
+}
+
+/// The solution to a game where every amazon is walled off from the other player's.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Endgame {
+    /// How many moves white can make in all of their regions together
+    pub white: MoveCount,
+    pub black: MoveCount,
+    /// The first move of the longest sequence found for the player to move, or `None` if they
+    /// have no moves
+    pub best_move: Option<Move>,
+    /// How many positions were searched
+    pub nodes: usize,
+    white_to_move: bool,
+}
+
+impl Endgame {
+    /// Solves `board`, searching until the `budget` runs out. `None` unless the board is
+    /// [partitioned](Board::is_partitioned).
+    pub fn solve(board: &Board, budget: Budget) -> Option<Self> {
+        let regions = board.regions();
+        if !regions.iter().all(Region::is_settled) {
+            return None;
+        }
+        let mut endgame = Endgame {
+            white: MoveCount::default(),
+            black: MoveCount::default(),
+            best_move: None,
+            nodes: 0,
+            white_to_move: board.white_to_move,
+        };
+        // The longest line found in any of the regions of the player to move
+        let mut best_line = 0;
+        // The player to move's regions are solved first, so they get the nodes they need to find a
+        // move before the budget runs out
+        let mover = if board.white_to_move {
+            Owner::White
+        } else {
+            Owner::Black
+        };
+        let regions = regions
+            .iter()
+            .filter(|region| region.owner == mover)
+            .chain(regions.iter().filter(|region| region.owner != mover));
+        for region in regions {
+            if region.owner == Owner::Dead {
+                continue;
+            }
+            let remaining = Budget {
+                max_nodes: budget.max_nodes.saturating_sub(endgame.nodes),
+                ..budget
+            };
+            let solution = solve_region(board, region, remaining);
+            endgame.nodes += solution.nodes;
+            let white_region = region.owner == Owner::White;
+            if white_region {
+                endgame.white = endgame.white + solution.moves;
+            } else {
+                endgame.black = endgame.black + solution.moves;
+            }
+            if white_region == board.white_to_move && solution.moves.lower > best_line {
+                best_line = solution.moves.lower;
+                endgame.best_move = solution.best_move;
+            }
+        }
+        Some(endgame)
+    }
+
+    /// Who wins with best play, or `None` if the solver couldn't tell. The player to move loses if
+    /// they can't make more moves than their opponent, since they run out first.
+    pub fn outcome(&self) -> Option<Outcome> {
+        let (mover, other) = if self.white_to_move {
+            (self.white, self.black)
+        } else {
+            (self.black, self.white)
+        };
+        let mover_wins = if mover.lower > other.upper {
+            true
+        } else if mover.upper <= other.lower {
+            false
+        } else {
+            return None;
+        };
+        Some(if mover_wins == self.white_to_move {
+            Outcome::WhiteWins
+        } else {
+            Outcome::BlackWins
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::c;
+
+    /// Plays out the longest line from `board` for the amazons in `region`, checking that every
+    /// move is legal, and returns how many moves it made.
+    fn play_out(board: &Board, region: &Region) -> usize {
+        let mut board = board.clone();
+        let mut region = region.clone();
+        let mut moves = 0;
+        loop {
+            let solution = solve_region(&board, &region, Budget::nodes(1_000_000));
+            let Some(mov) = solution.best_move else {
+                return moves;
+            };
+            board.white_to_move = region.owner == Owner::White;
+            assert!(board.is_legal(&mov), "{mov}\n{board}");
+            board.apply_move(&mov);
+            moves += 1;
+            region = board
+                .regions()
+                .into_iter()
+                .find(|next| next.amazons.contains(&mov.1))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fills_open_region() {
+        // White has a 4x4 box in the bottom left, with one amazon in it
+        let board: Board = "3B2B3/10/10/B8B/10/xxxxx5/4x5/4x4W/W3x4W/4x4W w 30"
+            .parse()
+            .unwrap();
+        let regions = board.regions();
+        let region = regions.iter().find(|r| r.amazons == [c!(a2)]).unwrap();
+        assert_eq!(region.size(), 15);
+        let solution = solve_region(&board, region, Budget::nodes(100_000));
+        assert_eq!(solution.moves, MoveCount::exact(15));
+        assert_eq!(play_out(&board, region), 15);
+    }
+
+    #[test]
+    fn test_defective_region() {
+        // The amazon on b2 can step to a1 or c1, but the arrow on b1 stops it reaching one from
+        // the other, so whichever it picks it has to fire back at b2 and leave the other empty
+        let board: Board = "3B2B3/10/10/B8B/10/10/W9/xxx7/xWxx5W/1x1x4W1 w 30"
+            .parse()
+            .unwrap();
+        let regions = board.regions();
+        let region = regions.iter().find(|r| r.amazons == [c!(b2)]).unwrap();
+        assert_eq!(region.size(), 2);
+        let solution = solve_region(&board, region, Budget::nodes(100_000));
+        assert_eq!(solution.moves, MoveCount::exact(1));
+        assert_eq!(play_out(&board, region), 1);
+    }
+
+    #[test]
+    fn test_node_limit() {
+        let board: Board = "3B2B3/10/10/B8B/10/xxxxx5/4x5/4x4W/W3x4W/4x4W w 30"
+            .parse()
+            .unwrap();
+        let regions = board.regions();
+        let region = regions.iter().find(|r| r.amazons == [c!(a2)]).unwrap();
+        let solution = solve_region(&board, region, Budget::nodes(3));
+        assert!(solution.moves.lower <= solution.moves.upper);
+        assert_eq!(solution.moves.upper, 15);
+        assert!(solution.moves.to_string().ends_with(" to 15"));
+        assert!(solution.best_move.is_some());
+        // Even without any budget there's a move to play
+        assert!(solve_region(&board, region, Budget::nodes(0))
+            .best_move
+            .is_some());
+    }
+
+    #[test]
+    fn test_endgame() {
+        // White has 3 empty squares around d1 and f1 to black's 4 around f10 and h10, and the
+        // amazons in the corners are trapped
+        let board: Board = "BxxxxB1BxB/xxxxx3xx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/\
+                            xxxxxxxxxx/xxxxxxxxxx/xxx2xxxxx/WxxW1WxxxW w 60"
+            .parse()
+            .unwrap();
+        let endgame = Endgame::solve(&board, Budget::nodes(100_000)).unwrap();
+        assert_eq!(endgame.white, MoveCount::exact(3));
+        assert_eq!(endgame.white.to_string(), "3");
+        assert_eq!(endgame.black, MoveCount::exact(4));
+        assert_eq!(endgame.outcome(), Some(Outcome::BlackWins));
+        assert!(board.is_legal(&endgame.best_move.unwrap()));
+
+        assert_eq!(
+            Endgame::solve(&Board::default(), Budget::nodes(100_000)),
+            None
+        );
+    }
+}
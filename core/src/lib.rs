@@ -1,12 +1,34 @@
+pub mod endgame;
+pub mod mcts;
+pub mod process;
+pub mod protocol;
+mod reader;
+pub mod record;
+pub mod regions;
+pub mod transposition;
+
 use core::fmt;
+pub use endgame::{Budget, Endgame};
 use lazy_static::lazy_static;
-use rand::seq::IteratorRandom;
+use log::{debug, info, log_enabled, trace, Level};
+pub use mcts::{Mcts, MctsConfig, Playout};
+use rand::{rngs::StdRng, seq::IteratorRandom, SeedableRng};
+pub use record::GameRecord;
+pub use regions::{Owner, Region};
 use std::{
     fmt::{Display, Formatter, Write},
     mem::swap,
     ops::Range,
-    time::{Duration, SystemTime},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
+pub use transposition::TranspositionTable;
+use transposition::{Bound, Entry};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Dim {
@@ -116,25 +138,68 @@ impl From<Coord> for PrettyCoord {
         PrettyCoord(row.into(), col.into())
     }
 }
-impl From<&str> for PrettyCoord {
-    fn from(value: &str) -> Self {
+/// Why a coordinate or move could not be parsed from its notation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// The coordinate didn't start with a column letter between `a` and `j`.
+    Column(String),
+    /// The coordinate didn't end with a row number between `1` and `10`.
+    Row(String),
+    /// The move wasn't of the form `a1-b2/c3`.
+    Format(String),
+    /// One of the coordinates of a move couldn't be parsed.
+    InMove(MovePart, Box<ParseError>),
+}
+
+/// The three coordinates that make up a move.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MovePart {
+    Piece,
+    Destination,
+    Arrow,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Column(seen) => write!(f, "invalid column in coordinate {seen:?}"),
+            ParseError::Row(seen) => write!(f, "invalid row in coordinate {seen:?}"),
+            ParseError::Format(seen) => {
+                write!(f, "expected a move like \"a1-b2/c3\", got {seen:?}")
+            }
+            ParseError::InMove(part, error) => {
+                let part = match part {
+                    MovePart::Piece => "amazon",
+                    MovePart::Destination => "destination",
+                    MovePart::Arrow => "arrow",
+                };
+                write!(f, "{error} for the {part}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for PrettyCoord {
+    type Err = ParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
         let mut s = value.chars();
-        let row = s.next().expect("Invalid row");
-        let col = s.collect::<String>();
-        let row = match row {
-            'a' => Dim::A,
-            'b' => Dim::B,
-            'c' => Dim::C,
-            'd' => Dim::D,
-            'e' => Dim::E,
-            'f' => Dim::F,
-            'g' => Dim::G,
-            'h' => Dim::H,
-            'i' => Dim::I,
-            'j' => Dim::J,
-            _ => panic!("Invalid row"),
+        let col = match s.next() {
+            Some('a') => Dim::A,
+            Some('b') => Dim::B,
+            Some('c') => Dim::C,
+            Some('d') => Dim::D,
+            Some('e') => Dim::E,
+            Some('f') => Dim::F,
+            Some('g') => Dim::G,
+            Some('h') => Dim::H,
+            Some('i') => Dim::I,
+            Some('j') => Dim::J,
+            _ => return Err(ParseError::Column(value.to_string())),
         };
-        let col = match col.as_ref() {
+        let row = match s.as_str() {
             "1" => Dim::A,
             "2" => Dim::B,
             "3" => Dim::C,
@@ -145,19 +210,32 @@ impl From<&str> for PrettyCoord {
             "8" => Dim::H,
             "9" => Dim::I,
             "10" => Dim::J,
-            _ => panic!("Invalid col"),
+            _ => return Err(ParseError::Row(value.to_string())),
         };
-        PrettyCoord(row, col)
+        Ok(PrettyCoord(col, row))
+    }
+}
+impl TryFrom<&str> for PrettyCoord {
+    type Error = ParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
     }
 }
 
 macro_rules! c {
     ($x:expr) => {
-        Coord::from(&PrettyCoord::from(stringify!($x).as_ref()))
+        $crate::Coord::from(
+            &stringify!($x)
+                .parse::<$crate::PrettyCoord>()
+                .expect("c! requires a valid coordinate"),
+        )
     };
 }
+#[cfg(test)]
+pub(crate) use c;
 
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum TileState {
     Empty,
     White,
@@ -165,7 +243,7 @@ pub enum TileState {
     Arrow,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Eq, PartialEq)]
 pub struct Move(pub Coord, pub Coord, pub Coord);
 impl Move {
     pub fn notation(&self) -> String {
@@ -179,20 +257,39 @@ impl Move {
             PrettyCoord::from(*arrow)
         )
     }
-    pub fn parse_notation(notation: &str) -> Option<Move> {
-        let mut iter = notation.trim().split('-');
-        let piece = iter.next()?;
-        let remainder = iter.next()?;
-        let mut iter = remainder.split('/');
-        let mov = iter.next()?;
-        let arrow = iter.next()?;
-        Some(Move(
-            Coord::from(&PrettyCoord::from(piece)),
-            Coord::from(&PrettyCoord::from(mov)),
-            Coord::from(&PrettyCoord::from(arrow)),
+    pub fn parse_notation(notation: &str) -> Result<Move, ParseError> {
+        notation.parse()
+    }
+}
+
+impl FromStr for Move {
+    type Err = ParseError;
+
+    fn from_str(notation: &str) -> Result<Self, Self::Err> {
+        let trimmed = notation.trim();
+        let format_error = || ParseError::Format(trimmed.to_string());
+        let (piece, remainder) = trimmed.split_once('-').ok_or_else(format_error)?;
+        let (mov, arrow) = remainder.split_once('/').ok_or_else(format_error)?;
+        let parse = |coord: &str, part| {
+            coord
+                .parse::<PrettyCoord>()
+                .map(|coord| Coord::from(&coord))
+                .map_err(|error| ParseError::InMove(part, Box::new(error)))
+        };
+        Ok(Move(
+            parse(piece, MovePart::Piece)?,
+            parse(mov, MovePart::Destination)?,
+            parse(arrow, MovePart::Arrow)?,
         ))
     }
 }
+impl TryFrom<&str> for Move {
+    type Error = ParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
 
 impl Display for Move {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -206,6 +303,59 @@ impl fmt::Debug for Move {
     }
 }
 
+/// The reasons a move can be rejected by [`Board::try_apply_move`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MoveError {
+    /// One of the coordinates does not lie on the board.
+    OutOfBounds(Coord),
+    /// There is no amazon on the starting square.
+    NoPiece(Coord),
+    /// The amazon on the starting square belongs to the player who is not moving.
+    WrongTurn(Coord),
+    /// The amazon cannot travel from the first square to the second.
+    Unreachable(Coord, Coord),
+    /// The arrow cannot be fired from the first square to the second.
+    ArrowUnreachable(Coord, Coord),
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveError::OutOfBounds(coord) => write!(f, "{coord} is not on the board"),
+            MoveError::NoPiece(coord) => {
+                write!(f, "there is no amazon on {}", PrettyCoord::from(*coord))
+            }
+            MoveError::WrongTurn(coord) => write!(
+                f,
+                "the amazon on {} belongs to the other player",
+                PrettyCoord::from(*coord)
+            ),
+            MoveError::Unreachable(from, to) => write!(
+                f,
+                "the amazon on {} cannot reach {}",
+                PrettyCoord::from(*from),
+                PrettyCoord::from(*to)
+            ),
+            MoveError::ArrowUnreachable(from, to) => write!(
+                f,
+                "an arrow cannot be fired from {} to {}",
+                PrettyCoord::from(*from),
+                PrettyCoord::from(*to)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// The state of a game, from the point of view of [`Board::outcome`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Ongoing,
+    WhiteWins,
+    BlackWins,
+}
+
 #[derive(Clone)]
 pub struct Board {
     // Track where the amazons are so we don't need to search the board for them
@@ -213,21 +363,26 @@ pub struct Board {
     // Track what state every square on the board is in
     pub tiles: [TileState; 100],
     pub collisions: u128,
+    pub white_to_move: bool,
+    // The number of moves played so far, by both players
+    pub ply: usize,
+    // Zobrist hash of the position, kept up to date by apply_move and unmake_move
+    pub hash: u64,
 }
 
 impl Default for Board {
     #[allow(clippy::needless_range_loop)]
     fn default() -> Self {
-        // Set up the pieces
+        // Set up the pieces, in the same order as `from_fen` would put them
         let pieces = [
-            c!(a4),
             c!(d1),
             c!(g1),
+            c!(a4),
             c!(j4),
             c!(a7),
+            c!(j7),
             c!(d10),
             c!(g10),
-            c!(j7),
         ];
         // Set up an empty board
         let mut tiles = [TileState::Empty; 100];
@@ -242,11 +397,15 @@ impl Default for Board {
             pieces,
             tiles,
             collisions: 0,
+            white_to_move: true,
+            ply: 0,
+            hash: 0,
         };
         for piece in pieces.iter() {
             board.toggle_collision(*piece);
         }
         // set up the collisions board properly
+        board.hash = board.compute_hash();
         board
     }
 }
@@ -255,9 +414,52 @@ impl Board {
     pub fn toggle_collision(&mut self, coord: usize) {
         self.collisions ^= 1 << coord;
     }
+    /// Computes the Zobrist hash of the position from scratch. `hash` should always be equal to
+    /// this, but is much cheaper to maintain.
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = if self.white_to_move {
+            0
+        } else {
+            *ZOBRIST_BLACK_TO_MOVE
+        };
+        for (coord, tile) in self.tiles.iter().enumerate() {
+            hash ^= match tile {
+                TileState::Empty => 0,
+                TileState::White => ZOBRIST_KEYS[coord][0],
+                TileState::Black => ZOBRIST_KEYS[coord][1],
+                TileState::Arrow => ZOBRIST_KEYS[coord][2],
+            };
+        }
+        hash
+    }
     pub fn reachable_squares(&self, coord: &Coord) -> ReachableIterator<'_> {
         ReachableIterator::new(self, coord)
     }
+    /// The same squares as [`Board::reachable_squares`], computed from `collisions` as a mask.
+    pub fn reachable_mask(&self, coord: &Coord) -> u128 {
+        queen_mask(self.collisions, *coord)
+    }
+    /// Appends the moves for the pieces in `range` to `moves`, in the same order as
+    /// [`Board::moves`] would produce them.
+    pub fn generate_moves(&self, range: Range<usize>, moves: &mut Vec<Move>) {
+        for piece_idx in range {
+            self.generate_piece_moves(piece_idx, moves);
+        }
+    }
+    pub fn generate_piece_moves(&self, piece_idx: usize, moves: &mut Vec<Move>) {
+        let piece = self.pieces[piece_idx];
+        // The piece leaves its square, so the arrow is allowed to pass through it
+        let occupied = self.collisions ^ (1 << piece);
+        for piece_dir in 0..8 {
+            for mov in ray_bits(occupied, piece, piece_dir) {
+                for arrow_dir in 0..8 {
+                    for arrow in ray_bits(occupied, mov, arrow_dir) {
+                        moves.push(Move(piece, mov, arrow));
+                    }
+                }
+            }
+        }
+    }
     pub fn moves(&self, range: Range<usize>) -> impl Iterator<Item = Move> + '_ {
         MoveIterator::new(self, range)
     }
@@ -276,8 +478,120 @@ impl Board {
     pub fn black_moves_boards(&self) -> impl Iterator<Item = (Move, Board)> + '_ {
         self.moves_boards(4..8)
     }
+    /// The indices into `pieces` of the amazons belonging to the player to move.
+    pub fn side_to_move_range(&self) -> Range<usize> {
+        if self.white_to_move {
+            0..4
+        } else {
+            4..8
+        }
+    }
+    pub fn legal_moves(&self) -> impl Iterator<Item = Move> + '_ {
+        self.moves(self.side_to_move_range())
+    }
+    pub fn legal_moves_boards(&self) -> impl Iterator<Item = (Move, Board)> + '_ {
+        self.moves_boards(self.side_to_move_range())
+    }
+    pub fn has_legal_moves(&self) -> bool {
+        // An amazon that can step anywhere can always fire back at the square it came from
+        self.pieces[self.side_to_move_range()]
+            .iter()
+            .any(|piece| self.reachable_mask(piece) != 0)
+    }
+    /// The player to move loses once they have no legal moves.
+    pub fn outcome(&self) -> Outcome {
+        if self.has_legal_moves() {
+            Outcome::Ongoing
+        } else if self.white_to_move {
+            Outcome::BlackWins
+        } else {
+            Outcome::WhiteWins
+        }
+    }
+
+    /// The squares the amazon on `piece` can move to. Empty if there isn't an amazon belonging to
+    /// the player to move on `piece`.
+    pub fn legal_destinations(&self, piece: Coord) -> impl Iterator<Item = Coord> {
+        let mask = if self.is_own_piece(piece) {
+            self.reachable_mask(&piece)
+        } else {
+            0
+        };
+        mask_coords(mask)
+    }
+    /// The squares an arrow can be fired at after moving the amazon on `piece` to `to`. Empty if
+    /// that isn't a legal move for the amazon.
+    pub fn legal_arrow_targets(&self, piece: Coord, to: Coord) -> impl Iterator<Item = Coord> {
+        let mask = if self.is_own_piece(piece) && self.is_reachable(piece, to, piece) {
+            // The piece leaves its square, so the arrow is allowed to pass through it
+            queen_mask(self.collisions ^ (1 << piece), to)
+        } else {
+            0
+        };
+        mask_coords(mask)
+    }
+    fn is_own_piece(&self, coord: Coord) -> bool {
+        matches!(
+            (self.tiles.get(coord), self.white_to_move),
+            (Some(TileState::White), true) | (Some(TileState::Black), false)
+        )
+    }
+
+    /// Whether the square at `to` can be reached from `from` by a queen move. Every square along
+    /// the way must be empty, except for `vacated`, which is treated as empty regardless.
+    fn is_reachable(&self, from: Coord, to: Coord, vacated: Coord) -> bool {
+        MOVES[from].iter().any(|moves_in_dir| {
+            for &square in moves_in_dir {
+                if square != vacated && self.tiles[square] != TileState::Empty {
+                    return false;
+                }
+                if square == to {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+
+    /// Checks that `mov` is a legal move for the player to move without applying it.
+    pub fn check_move(&self, mov: &Move) -> Result<(), MoveError> {
+        let Move(piece_coord, move_coord, arrow_coord) = *mov;
+        for coord in [piece_coord, move_coord, arrow_coord] {
+            if coord >= 100 {
+                return Err(MoveError::OutOfBounds(coord));
+            }
+        }
+        match (self.tiles[piece_coord], self.white_to_move) {
+            (TileState::White, true) | (TileState::Black, false) => {}
+            (TileState::White, false) | (TileState::Black, true) => {
+                return Err(MoveError::WrongTurn(piece_coord))
+            }
+            _ => return Err(MoveError::NoPiece(piece_coord)),
+        }
+        if !self.is_reachable(piece_coord, move_coord, piece_coord) {
+            return Err(MoveError::Unreachable(piece_coord, move_coord));
+        }
+        // The amazon has left its starting square, so the arrow may pass through or land on it
+        if !self.is_reachable(move_coord, arrow_coord, piece_coord) {
+            return Err(MoveError::ArrowUnreachable(move_coord, arrow_coord));
+        }
+        Ok(())
+    }
 
-    pub fn apply_move(&mut self, mov: &Move) {
+    pub fn is_legal(&self, mov: &Move) -> bool {
+        self.check_move(mov).is_ok()
+    }
+
+    /// Applies `mov` if it is legal for the player to move, otherwise leaves the board untouched.
+    pub fn try_apply_move(&mut self, mov: &Move) -> Result<(), MoveError> {
+        self.check_move(mov)?;
+        self.apply_move(mov);
+        Ok(())
+    }
+
+    /// Applies `mov` without checking that it is legal. The returned [`UndoInfo`] can be passed
+    /// to [`Board::unmake_move`] to restore the board to its current state.
+    pub fn apply_move(&mut self, mov: &Move) -> UndoInfo {
         let Move(piece_coord, move_coord, arrow_coord) = *mov;
         let piece_index = (0..8)
             .find(|idx: &usize| self.pieces[*idx] == piece_coord)
@@ -297,9 +611,63 @@ impl Board {
         // Place the arrow
         self.tiles[arrow_coord] = TileState::Arrow;
         self.toggle_collision(arrow_coord);
+        // Pass the turn
+        self.white_to_move = !self.white_to_move;
+        self.ply += 1;
+        self.hash ^= move_hash(mov, piece_index);
+        UndoInfo {
+            mov: *mov,
+            piece_index,
+        }
+    }
+
+    /// Reverses the [`Board::apply_move`] that produced `undo`. Moves must be unmade in the
+    /// reverse order they were made.
+    pub fn unmake_move(&mut self, undo: UndoInfo) {
+        let UndoInfo {
+            mov: Move(piece_coord, move_coord, arrow_coord),
+            piece_index,
+        } = undo;
+        // Take the turn back
+        self.white_to_move = !self.white_to_move;
+        self.ply -= 1;
+        self.hash ^= move_hash(&undo.mov, piece_index);
+        // Remove the arrow first, it may have been fired into the square the piece came from
+        self.tiles[arrow_coord] = TileState::Empty;
+        self.toggle_collision(arrow_coord);
+        // Return the piece
+        self.tiles[move_coord] = TileState::Empty;
+        self.toggle_collision(move_coord);
+        self.pieces[piece_index] = piece_coord;
+        self.toggle_collision(piece_coord);
+        self.tiles[piece_coord] = if piece_index < 4 {
+            TileState::White
+        } else {
+            TileState::Black
+        };
     }
 }
 
+/// The value to XOR into the Zobrist hash when applying or unmaking a move. Since XOR is its own
+/// inverse, both directions use the same value.
+#[inline]
+fn move_hash(mov: &Move, piece_index: usize) -> u64 {
+    let Move(piece_coord, move_coord, arrow_coord) = *mov;
+    let color = if piece_index < 4 { 0 } else { 1 };
+    ZOBRIST_KEYS[piece_coord][color]
+        ^ ZOBRIST_KEYS[move_coord][color]
+        ^ ZOBRIST_KEYS[arrow_coord][2]
+        ^ *ZOBRIST_BLACK_TO_MOVE
+}
+
+/// Everything needed to reverse a move applied with [`Board::apply_move`].
+#[derive(Clone, Copy, Debug)]
+pub struct UndoInfo {
+    mov: Move,
+    // Which entry of `pieces` was moved, so we don't need to search for it again
+    piece_index: usize,
+}
+
 pub struct MoveIterator<'a> {
     board: &'a Board,
     range: Range<usize>,
@@ -425,6 +793,7 @@ impl<'a> Iterator for PieceMoveIterator<'a> {
                     // arrow.
                     // Time to advance to the next square.
                     self.piece_dist += 1;
+                    self.arrow_dir = 0;
                 } else {
                     // We have walked into a piece or arrow, stop walking forward.
                     break;
@@ -535,10 +904,107 @@ lazy_static! {
         collisions(&dirs[7]),
     ]).collect();
 }
+lazy_static! {
+    // Zobrist keys for a white amazon, black amazon and arrow on every square. These are generated
+    // from a fixed seed so that hashes are stable between runs and can be stored.
+    static ref ZOBRIST_KEYS: [[u64; 3]; 100] = {
+        let mut state = 0x616d617a6f6e73; // "amazons"
+        let mut keys = [[0; 3]; 100];
+        for square in keys.iter_mut() {
+            for key in square.iter_mut() {
+                *key = splitmix64(&mut state);
+            }
+        }
+        keys
+    };
+    static ref ZOBRIST_BLACK_TO_MOVE: u64 = splitmix64(&mut 0x626c61636b); // "black"
+}
+/// A small, well distributed PRNG. Only used to fill in the Zobrist tables.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
 fn collisions(coords: &[Coord]) -> u128 {
     coords.iter().map(|c| 1 << c).sum()
 }
 
+// Whether each direction in MOVES walks towards higher indices (Up+Left, Up, Up+Right, Right) or
+// lower indices (Left, Down+Right, Down, Down+Left).
+const ASCENDING: [bool; 8] = [false, true, true, true, true, false, false, false];
+
+/// The squares reachable from `from` in direction `dir`, stopping before the first square that is
+/// set in `occupied`.
+#[inline]
+fn ray_mask(occupied: u128, from: Coord, dir: usize) -> u128 {
+    let ray = MOVE_COLLISIONS[from][dir];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        ray
+    } else if ASCENDING[dir] {
+        // The nearest blocker is the lowest bit, keep everything below it
+        let blocker = blockers.trailing_zeros();
+        ray & ((1 << blocker) - 1)
+    } else {
+        // The nearest blocker is the highest bit, keep everything above it
+        let blocker = 127 - blockers.leading_zeros();
+        ray & !((2 << blocker) - 1)
+    }
+}
+
+/// The squares reachable from `from` by a queen move, given the `occupied` squares.
+#[inline]
+fn queen_mask(occupied: u128, from: Coord) -> u128 {
+    (0..8).fold(0, |mask, dir| mask | ray_mask(occupied, from, dir))
+}
+
+/// Iterates over the squares set in a ray mask, starting with the square nearest to the origin.
+/// This matches the order the squares appear in MOVES.
+struct RayBits {
+    mask: u128,
+    ascending: bool,
+}
+impl Iterator for RayBits {
+    type Item = Coord;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.mask == 0 {
+            None
+        } else if self.ascending {
+            let coord = self.mask.trailing_zeros();
+            self.mask &= self.mask - 1;
+            Some(coord as Coord)
+        } else {
+            let coord = 127 - self.mask.leading_zeros();
+            self.mask ^= 1 << coord;
+            Some(coord as Coord)
+        }
+    }
+}
+fn ray_bits(occupied: u128, from: Coord, dir: usize) -> RayBits {
+    RayBits {
+        mask: ray_mask(occupied, from, dir),
+        ascending: ASCENDING[dir],
+    }
+}
+
+/// Iterates over the squares set in any mask, in ascending order.
+pub fn mask_coords(mut mask: u128) -> impl Iterator<Item = Coord> {
+    std::iter::from_fn(move || {
+        if mask == 0 {
+            None
+        } else {
+            let coord = mask.trailing_zeros() as Coord;
+            mask &= mask - 1;
+            Some(coord)
+        }
+    })
+}
+
 impl Display for Board {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_str("   a b c d e f g h i j\n")?;
@@ -557,16 +1023,233 @@ impl Display for Board {
         Ok(())
     }
 }
+/// Why a position could not be parsed by [`Board::from_fen`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FenError {
+    /// There weren't three space separated fields: the rows, the side to move and the ply.
+    Fields(String),
+    /// There weren't 10 rows separated by `/`.
+    RowCount(usize),
+    /// A row, numbered as on the board, contained something other than `W`, `B`, `x` or a count
+    /// of empty squares.
+    Character { row: usize, found: char },
+    /// A row, numbered as on the board, didn't describe exactly 10 squares.
+    RowLength { row: usize, squares: usize },
+    /// Each player must have exactly 4 amazons.
+    Amazons { white: usize, black: usize },
+    /// The side to move wasn't `w` or `b`.
+    SideToMove(String),
+    /// The ply wasn't a number.
+    Ply(String),
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenError::Fields(seen) => write!(
+                f,
+                "expected rows, side to move and ply separated by spaces, got {seen:?}"
+            ),
+            FenError::RowCount(rows) => write!(f, "expected 10 rows, got {rows}"),
+            FenError::Character { row, found } => {
+                write!(f, "unexpected {found:?} in row {row}")
+            }
+            FenError::RowLength { row, squares } => {
+                write!(f, "row {row} has {squares} squares instead of 10")
+            }
+            FenError::Amazons { white, black } => write!(
+                f,
+                "each player needs 4 amazons, white has {white} and black has {black}"
+            ),
+            FenError::SideToMove(seen) => {
+                write!(f, "expected \"w\" or \"b\" to move, got {seen:?}")
+            }
+            FenError::Ply(seen) => write!(f, "invalid ply {seen:?}"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+impl Board {
+    /// A compact description of the position, modelled on chess's FEN. The rows are listed from
+    /// 10 down to 1 and separated by `/`, with `W` for a white amazon, `B` for a black amazon, `x`
+    /// for an arrow and a number for a run of empty squares. They are followed by `w` or `b` for
+    /// the side to move, and the ply. The starting position is
+    /// `3B2B3/10/10/B8B/10/10/W8W/10/10/3W2W3 w 0`.
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for row in (0..10).rev() {
+            let mut empty = 0;
+            for col in 0..10 {
+                let tile = self.tiles[row * 10 + col];
+                if tile == TileState::Empty {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    write!(fen, "{empty}").expect("Writing to a String can't fail");
+                    empty = 0;
+                }
+                fen.push(match tile {
+                    TileState::White => 'W',
+                    TileState::Black => 'B',
+                    _ => 'x',
+                });
+            }
+            if empty > 0 {
+                write!(fen, "{empty}").expect("Writing to a String can't fail");
+            }
+            if row > 0 {
+                fen.push('/');
+            }
+        }
+        let side = if self.white_to_move { 'w' } else { 'b' };
+        write!(fen, " {side} {}", self.ply).expect("Writing to a String can't fail");
+        fen
+    }
+
+    /// Parses a position written by [`Board::to_fen`].
+    pub fn from_fen(fen: &str) -> Result<Board, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let [rows, side, ply] = fields[..] else {
+            return Err(FenError::Fields(fen.to_string()));
+        };
+        let rows: Vec<&str> = rows.split('/').collect();
+        if rows.len() != 10 {
+            return Err(FenError::RowCount(rows.len()));
+        }
+        let mut tiles = [TileState::Empty; 100];
+        let mut white = vec![];
+        let mut black = vec![];
+        for (i, text) in rows.iter().enumerate() {
+            let row = 9 - i;
+            let mut col = 0;
+            let mut chars = text.chars().peekable();
+            while let Some(c) = chars.next() {
+                let squares = match c {
+                    '1'..='9' => {
+                        let mut count = c.to_digit(10).unwrap_or_default() as usize;
+                        // Only 10 has two digits
+                        if c == '1' && chars.peek() == Some(&'0') {
+                            chars.next();
+                            count = 10;
+                        }
+                        count
+                    }
+                    'W' | 'B' | 'x' => {
+                        if col < 10 {
+                            let coord = row * 10 + col;
+                            tiles[coord] = match c {
+                                'W' => {
+                                    white.push(coord);
+                                    TileState::White
+                                }
+                                'B' => {
+                                    black.push(coord);
+                                    TileState::Black
+                                }
+                                _ => TileState::Arrow,
+                            };
+                        }
+                        1
+                    }
+                    found => {
+                        return Err(FenError::Character {
+                            row: row + 1,
+                            found,
+                        })
+                    }
+                };
+                col += squares;
+            }
+            if col != 10 {
+                return Err(FenError::RowLength {
+                    row: row + 1,
+                    squares: col,
+                });
+            }
+        }
+        if white.len() != 4 || black.len() != 4 {
+            return Err(FenError::Amazons {
+                white: white.len(),
+                black: black.len(),
+            });
+        }
+        let white_to_move = match side {
+            "w" => true,
+            "b" => false,
+            _ => return Err(FenError::SideToMove(side.to_string())),
+        };
+        let ply = ply.parse().map_err(|_| FenError::Ply(ply.to_string()))?;
+        // Moves are generated piece by piece, so keep each colour sorted by coordinate to search
+        // the same position in the same order however it was set up
+        white.sort_unstable();
+        black.sort_unstable();
+        let mut pieces = [0; 8];
+        pieces[0..4].copy_from_slice(&white);
+        pieces[4..8].copy_from_slice(&black);
+        let mut board = Board {
+            pieces,
+            tiles,
+            collisions: 0,
+            white_to_move,
+            ply,
+            hash: 0,
+        };
+        for (coord, tile) in tiles.iter().enumerate() {
+            if *tile != TileState::Empty {
+                board.toggle_collision(coord);
+            }
+        }
+        board.hash = board.compute_hash();
+        Ok(board)
+    }
+}
+
+impl FromStr for Board {
+    type Err = FenError;
+
+    fn from_str(fen: &str) -> Result<Self, Self::Err> {
+        Board::from_fen(fen)
+    }
+}
+
+/// Two boards are equal if they have the same position, regardless of the order their amazons
+/// are stored in.
+impl PartialEq for Board {
+    fn eq(&self, other: &Self) -> bool {
+        self.tiles == other.tiles
+            && self.white_to_move == other.white_to_move
+            && self.ply == other.ply
+    }
+}
+impl Eq for Board {}
+
 impl std::fmt::Debug for Board {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         Display::fmt(&self, f)
     }
 }
-pub fn random_white(board: &Board) -> Option<Move> {
-    board.black_moves().choose(&mut rand::thread_rng())
+
+/// Plays a uniformly random legal move for whichever side is to move. The same seed always gives
+/// the same moves, so a game against it can be replayed exactly.
+#[derive(Clone, Debug)]
+pub struct RandomPlayer {
+    rng: StdRng,
 }
-pub fn random_black(board: &Board) -> Option<Move> {
-    board.white_moves().choose(&mut rand::thread_rng())
+
+impl RandomPlayer {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// A random move for the side to move, or `None` if they have lost.
+    pub fn choose_move(&mut self, board: &Board) -> Option<Move> {
+        board.legal_moves().choose(&mut self.rng)
+    }
 }
 
 pub fn moves_heuristic(board: &Board) -> f64 {
@@ -706,122 +1389,659 @@ pub fn better_reachable_heuristic(board: &Board) -> f64 {
         .sum()
 }
 
+/// How many queen moves the nearest of the amazons in `range` needs to reach each square,
+/// through empty squares. `u8::MAX` for squares they can't reach.
+fn queen_distances(board: &Board, range: Range<usize>) -> [u8; 100] {
+    let mut distances = [u8::MAX; 100];
+    let mut frontier: u128 = board.pieces[range].iter().map(|&piece| 1 << piece).sum();
+    let mut reached = frontier;
+    let mut distance = 1;
+    while frontier != 0 {
+        let next = mask_coords(frontier)
+            .fold(0, |mask, coord| mask | queen_mask(board.collisions, coord))
+            & !reached;
+        for coord in mask_coords(next) {
+            distances[coord] = distance;
+        }
+        reached |= next;
+        frontier = next;
+        distance += 1;
+    }
+    distances
+}
+
+/// Counts the squares in regions that only one player's amazons can reach, and splits the
+/// contested regions square by square between whichever player can get there in fewer moves.
+pub fn territory_heuristic(board: &Board) -> f64 {
+    let white = queen_distances(board, 0..4);
+    let black = queen_distances(board, 4..8);
+    board
+        .regions()
+        .iter()
+        .map(|region| match region.owner {
+            Owner::White => region.size() as f64,
+            Owner::Black => -(region.size() as f64),
+            Owner::Dead => 0.0,
+            Owner::Contested => mask_coords(region.squares)
+                .map(|coord| match white[coord].cmp(&black[coord]) {
+                    std::cmp::Ordering::Less => 1.0,
+                    std::cmp::Ordering::Greater => -1.0,
+                    std::cmp::Ordering::Equal => 0.0,
+                })
+                .sum(),
+        })
+        .sum()
+}
+
 // TODO best reachable heuristic:
 // factor in the number of paths to a square
 // if you can reach the whole board in 3 moves, but there is only one path to each of those
 // squares, then you have a severe choke point.
 
-const HEURISTIC: fn(&Board) -> MMT = better_reachable_heuristic;
-// const HEURISTIC: fn(&Board) -> MMT = moves_heuristic;
+/// A static evaluation of a position, used at the leaves of the search. Scores are from white's
+/// point of view.
+pub trait Evaluator: Send + Sync {
+    /// The name used to select this evaluator from the command line.
+    fn name(&self) -> &'static str;
+    fn evaluate(&self, board: &Board) -> MMT;
+}
+
+impl fmt::Debug for dyn Evaluator {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub struct MovesHeuristic;
+impl Evaluator for MovesHeuristic {
+    fn name(&self) -> &'static str {
+        "moves"
+    }
+    fn evaluate(&self, board: &Board) -> MMT {
+        moves_heuristic(board)
+    }
+}
+
+pub struct AreaHeuristic;
+impl Evaluator for AreaHeuristic {
+    fn name(&self) -> &'static str {
+        "area"
+    }
+    fn evaluate(&self, board: &Board) -> MMT {
+        area_heuristic(board) as MMT
+    }
+}
+
+pub struct ReachableHeuristic;
+impl Evaluator for ReachableHeuristic {
+    fn name(&self) -> &'static str {
+        "reachable"
+    }
+    fn evaluate(&self, board: &Board) -> MMT {
+        reachable_heuristic(board) as MMT
+    }
+}
+
+pub struct BetterReachableHeuristic;
+impl Evaluator for BetterReachableHeuristic {
+    fn name(&self) -> &'static str {
+        "better-reachable"
+    }
+    fn evaluate(&self, board: &Board) -> MMT {
+        better_reachable_heuristic(board)
+    }
+}
+
+pub struct TerritoryHeuristic;
+impl Evaluator for TerritoryHeuristic {
+    fn name(&self) -> &'static str {
+        "territory"
+    }
+    fn evaluate(&self, board: &Board) -> MMT {
+        territory_heuristic(board)
+    }
+}
+
+/// Every evaluator, the first of which is the default.
+pub const EVALUATORS: [&dyn Evaluator; 5] = [
+    &BetterReachableHeuristic,
+    &TerritoryHeuristic,
+    &ReachableHeuristic,
+    &AreaHeuristic,
+    &MovesHeuristic,
+];
+
+pub fn evaluator_by_name(name: &str) -> Option<&'static dyn Evaluator> {
+    EVALUATORS
+        .into_iter()
+        .find(|evaluator| evaluator.name() == name)
+}
+
 const TIME_PER_TURN: Duration = Duration::from_secs(10);
+/// How many positions [`minimax`] lets the [`Endgame`] solver search before falling back to a
+/// normal search. Open regions take a few hundred, so this only runs out on awkward defective
+/// ones, and takes well under a second.
+const ENDGAME_MAX_NODES: usize = 200_000;
+/// How many positions the [`Endgame`] solver may search at each partitioned node inside the
+/// search. Enough to count the moves in open regions, but it gives up on defective ones quickly.
+const ENDGAME_PROBE_NODES: usize = 1_000;
 #[allow(clippy::upper_case_acronyms)]
 pub type MMT = f64;
+/// The score of a position where the player to move has won. Winning sooner is scored slightly
+/// higher, so the engine doesn't put off finishing a won game.
+pub const WIN: MMT = 1_000_000.0;
+
+/// The static evaluation of `board` from the point of view of the player to move.
+fn evaluate(evaluator: &dyn Evaluator, board: &Board) -> MMT {
+    if board.white_to_move {
+        evaluator.evaluate(board)
+    } else {
+        -evaluator.evaluate(board)
+    }
+}
+
+/// Limits on how much work a call to [`minimax`] may do. Limits that are `None` are not enforced,
+/// and the search stops as soon as any one of them is reached.
+#[derive(Clone, Debug)]
+pub struct SearchLimits {
+    /// A fixed amount of time to spend on this move
+    pub move_time: Option<Duration>,
+    /// Time left on the player's game clock
+    pub remaining: Option<Duration>,
+    /// Time added to the player's game clock after every move
+    pub increment: Duration,
+    pub max_depth: Option<usize>,
+    pub max_nodes: Option<usize>,
+    /// Set from another thread to make the search return as soon as possible
+    pub stop: Option<Arc<AtomicBool>>,
+    /// How many threads [`minimax`] searches with. The search is deterministic with one thread.
+    pub threads: usize,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            move_time: Some(TIME_PER_TURN),
+            remaining: None,
+            increment: Duration::ZERO,
+            max_depth: None,
+            max_nodes: None,
+            stop: None,
+            threads: 1,
+        }
+    }
+}
+
+impl SearchLimits {
+    /// No limits at all, the search will only stop once it reaches the end of the game.
+    pub fn infinite() -> Self {
+        Self {
+            move_time: None,
+            ..Default::default()
+        }
+    }
+
+    /// How long we can afford to spend searching `board`.
+    pub fn time_budget(&self, board: &Board) -> Option<Duration> {
+        let clock_budget = self.remaining.map(|remaining| {
+            // Every move fires an arrow into an empty square, so the game can't last longer than
+            // the number of empty squares. In practice it ends well before that.
+            let empty_squares = 92usize.saturating_sub(board.ply);
+            let moves_to_go = (empty_squares / 4).max(4) as u32;
+            let budget = remaining / moves_to_go + self.increment;
+            // Leave a little slack so we never run the clock all the way down
+            budget.min(remaining.mul_f64(0.9))
+        });
+        match (self.move_time, clock_budget) {
+            (Some(move_time), Some(clock_budget)) => Some(move_time.min(clock_budget)),
+            (move_time, clock_budget) => move_time.or(clock_budget),
+        }
+    }
+}
+
+/// The log target for the line [`minimax`] logs after completing each depth, e.g.
+/// `depth 3 score 1.5 nodes 12345 time 250 pv d1-d7/g7 g10-g8/e6 a4-b4/a4`. The score is from the
+/// point of view of the player to move, and the time is in milliseconds. [`Mcts`] logs the same
+/// line with `winrate` in place of `score`: the chance that the player to move wins, from 0 to 1,
+/// which can't be compared with a minimax score.
+pub const SEARCH_INFO_TARGET: &str = "search";
+
+/// How many nodes to visit between checking the clock. Checking every node would be wasteful, but
+/// a few hundred nodes only take a fraction of a millisecond.
+const CLOCK_CHECK_INTERVAL: usize = 256;
+
+/// State shared by every node of a search.
+pub struct SearchContext<'a> {
+    /// How many nodes have been visited so far
+    pub count: usize,
+    pub deadline: Option<Instant>,
+    pub max_nodes: Option<usize>,
+    /// Set once the search has hit one of its limits, after which every node returns immediately
+    pub stopped: bool,
+    pub evaluator: &'a dyn Evaluator,
+    pub tt: &'a TranspositionTable,
+    /// Checked along with the clock, the search stops once this is set
+    pub stop_flag: Option<&'a AtomicBool>,
+    /// How many positions the [`Endgame`] solver may search at each partitioned node, or 0 to
+    /// never use it
+    pub endgame_nodes: usize,
+    /// How many times the transposition table was probed, and how many of those found an entry.
+    /// Kept here rather than in the table, which is shared between threads.
+    pub tt_probes: usize,
+    pub tt_hits: usize,
+    // The node count at which to next check the clock
+    next_clock_check: usize,
+}
+
+impl<'a> SearchContext<'a> {
+    pub fn new(
+        evaluator: &'a dyn Evaluator,
+        deadline: Option<Instant>,
+        max_nodes: Option<usize>,
+        tt: &'a TranspositionTable,
+    ) -> Self {
+        Self {
+            count: 0,
+            deadline,
+            max_nodes,
+            stopped: false,
+            evaluator,
+            tt,
+            stop_flag: None,
+            endgame_nodes: 0,
+            tt_probes: 0,
+            tt_hits: 0,
+            next_clock_check: CLOCK_CHECK_INTERVAL,
+        }
+    }
+
+    /// Whether the search has hit one of its limits.
+    pub fn should_stop(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+        if self.max_nodes.is_some_and(|max| self.count >= max) {
+            self.stopped = true;
+        } else if self.count >= self.next_clock_check {
+            self.next_clock_check = self.count + CLOCK_CHECK_INTERVAL;
+            self.stopped = self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+                || self
+                    .stop_flag
+                    .is_some_and(|stop| stop.load(Ordering::Relaxed));
+        }
+        self.stopped
+    }
+}
+
+/// Alpha-beta search that clones a new board for every child. Scores are from the point of view of
+/// the player to move, and are fail-soft: if the score is outside of `alpha..beta`, it is only a
+/// bound on the true score.
+///
+/// This visits exactly the same nodes as [`_minimax_in_place`] with an empty transposition table,
+/// and is kept around to compare against.
 pub fn _minimax(
     board: &Board,
     depth: usize,
-    maxing: bool,
     alpha: MMT,
     beta: MMT,
     c: &mut usize,
-    timeout: Option<SystemTime>,
-) -> (Option<(Move, Board)>, MMT) {
+    deadline: Option<Instant>,
+    evaluator: &dyn Evaluator,
+) -> (Option<Move>, MMT) {
     *c += 1;
+    if depth == 0 || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return (None, evaluate(evaluator, board));
+    }
+    let mut alpha = alpha;
+    let mut best = (None, -(WIN + depth as MMT));
+    for (mov, board) in board.legal_moves_boards() {
+        let (_, mm) = _minimax(&board, depth - 1, -beta, -alpha, c, deadline, evaluator);
+        let mm = -mm;
+        if best.0.is_none() || mm > best.1 {
+            best = (Some(mov), mm);
+        }
+        alpha = alpha.max(mm);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+/// Alpha-beta search that explores the tree by making and unmaking moves on a single board. Scores
+/// are from the point of view of the player to move, and are fail-soft: if the score is outside of
+/// `alpha..beta`, it is only a bound on the true score.
+///
+/// Positions are cached in the transposition table, which is used both to skip positions that have
+/// already been searched deeply enough, and to search the best move from an earlier search first.
+/// `pv` is the principal variation from a previous search; when we are on it, its next move is
+/// searched before anything else. The principal variation of this search is written to `pv_out`.
+pub fn _minimax_in_place(
+    board: &mut Board,
+    depth: usize,
+    alpha: MMT,
+    beta: MMT,
+    ctx: &mut SearchContext,
+    pv: &[Move],
+    pv_out: &mut Vec<Move>,
+) -> (Option<Move>, MMT) {
+    ctx.count += 1;
+    pv_out.clear();
+    if ctx.should_stop() || depth == 0 {
+        return (None, evaluate(ctx.evaluator, board));
+    }
     let mut alpha = alpha;
     let mut beta = beta;
-    if depth == 0 || (timeout.is_some() && SystemTime::now() > timeout.unwrap()) {
-        // if depth == 0 || (*c % POLL_INTERVAL == 0 && SystemTime::now() > timeout) {
-        (None, HEURISTIC(board))
-    } else if depth > 111111 {
-        // TODO disabling this branch temporarily
-        if maxing {
-            let mut moves: Vec<(Option<(Move, Board)>, MMT)> = board
-                .white_moves_boards()
-                .map(|(mov, board)| {
-                    let (_, mm) = _minimax(&board, depth - 1, !maxing, alpha, beta, c, timeout);
-                    alpha = alpha.max(mm);
-                    (Some((mov, board)), mm)
-                })
-                .collect();
-            moves.sort_by(|(_, a), (_, b)| a.total_cmp(b));
-            moves
-                .into_iter()
-                .take_while(|(_, mm)| mm <= &beta)
-                .max_by(|(_, a), (_, b)| a.total_cmp(b))
-                .unwrap_or((None, MMT::MAX))
+    let mut tt_move = None;
+    ctx.tt_probes += 1;
+    let entry = ctx.tt.probe(board.hash);
+    ctx.tt_hits += usize::from(entry.is_some());
+    if let Some(entry) = entry {
+        // Guard against hash collisions, the stored move might not be legal here
+        tt_move = entry.best_move.filter(|mov| board.is_legal(mov));
+        // Don't cut the principal variation short, we want to search all of it
+        if entry.depth >= depth && tt_move.is_some() && pv.is_empty() {
+            match entry.bound {
+                Bound::Exact => {
+                    pv_out.extend(tt_move);
+                    return (tt_move, entry.score);
+                }
+                Bound::Lower => alpha = alpha.max(entry.score),
+                Bound::Upper => beta = beta.min(entry.score),
+            }
+            if alpha >= beta {
+                pv_out.extend(tt_move);
+                return (tt_move, entry.score);
+            }
+        }
+    }
+    // Only positions where the amazons are walled off from each other can be solved, and checking
+    // that is much cheaper than asking the solver
+    if ctx.endgame_nodes > 0 && board.is_partitioned() {
+        let budget = Budget {
+            max_nodes: ctx.endgame_nodes,
+            deadline: ctx.deadline,
+            stop: ctx.stop_flag,
+        };
+        if let Some(endgame) = Endgame::solve(board, budget) {
+            ctx.count += endgame.nodes;
+            if let Some((mover_wins, plies)) = endgame_outcome(board, &endgame) {
+                // Scored like the end of the game `plies` plies further down the tree
+                let score = WIN + depth as MMT - plies as MMT;
+                let score = if mover_wins { score } else { -score };
+                if endgame.best_move.is_some() {
+                    ctx.tt.store(Entry::new(
+                        board.hash,
+                        depth,
+                        Bound::Exact,
+                        score,
+                        endgame.best_move,
+                    ));
+                }
+                pv_out.extend(endgame.best_move);
+                return (endgame.best_move, score);
+            }
+        }
+    }
+    let original_alpha = alpha;
+    let pv_move = pv.first().copied().filter(|mov| board.is_legal(mov));
+    // Search the principal variation first, then the best move from the transposition table.
+    // These are the most likely to be best, which makes the rest of the search cheaper.
+    let mut moves = Vec::with_capacity(512);
+    moves.extend(pv_move);
+    moves.extend(tt_move.filter(|mov| Some(*mov) != pv_move));
+    let first_moves = moves.clone();
+    let mut pieces = board.side_to_move_range();
+    let mut best = (None, -(WIN + depth as MMT));
+    let mut child_pv = Vec::new();
+    'search: loop {
+        for mov in moves.iter() {
+            let child_pv_in = if Some(*mov) == pv_move { &pv[1..] } else { &[] };
+            let undo = board.apply_move(mov);
+            let (_, mm) = _minimax_in_place(
+                board,
+                depth - 1,
+                -beta,
+                -alpha,
+                ctx,
+                child_pv_in,
+                &mut child_pv,
+            );
+            board.unmake_move(undo);
+            let mm = -mm;
+            if best.0.is_none() || mm > best.1 {
+                best = (Some(*mov), mm);
+                pv_out.clear();
+                pv_out.push(*mov);
+                pv_out.extend_from_slice(&child_pv);
+            }
+            alpha = alpha.max(mm);
+            if alpha >= beta || ctx.stopped {
+                // The opponent will never allow this position, or we are out of time
+                break 'search;
+            }
+        }
+        let Some(piece_idx) = pieces.next() else {
+            break;
+        };
+        // We can't borrow the board while we are modifying it, so collect the moves up front.
+        // Doing it one piece at a time saves generating moves that will be pruned anyway.
+        moves.clear();
+        board.generate_piece_moves(piece_idx, &mut moves);
+        moves.retain(|mov| !first_moves.contains(mov));
+    }
+    // A search that was stopped early is incomplete and shouldn't be remembered
+    if best.0.is_some() && !ctx.stopped {
+        let bound = if best.1 <= original_alpha {
+            Bound::Upper
+        } else if best.1 >= beta {
+            Bound::Lower
         } else {
-            let mut moves: Vec<(Option<(Move, Board)>, MMT)> = board
-                .black_moves_boards()
-                .map(|(mov, board)| {
-                    let (_, mm) = _minimax(&board, depth - 1, !maxing, alpha, beta, c, timeout);
-                    beta = beta.min(mm);
-                    (Some((mov, board)), mm)
+            Bound::Exact
+        };
+        ctx.tt
+            .store(Entry::new(board.hash, depth, bound, best.1, best.0));
+    }
+    best
+}
+
+/// Searches for the best move for the player to move using iterative deepening, until one of the
+/// `limits` is reached. The returned score is from white's point of view.
+///
+/// With more than one of `limits.threads`, the extra threads run the same search alongside this
+/// one, sharing the transposition table (Lazy SMP). They don't report results of their own, but the
+/// entries they store let this thread skip work and order moves better.
+pub fn minimax(
+    board: &Board,
+    tt: &mut TranspositionTable,
+    limits: &SearchLimits,
+    evaluator: &dyn Evaluator,
+) -> (Option<Move>, MMT) {
+    let start_time = Instant::now();
+    let deadline = limits.time_budget(board).map(|budget| start_time + budget);
+    if let Some(result) = solve_endgame(board, limits, deadline, start_time) {
+        return result;
+    }
+    tt.new_search();
+    let tt = &*tt;
+    // There's no point searching past the end of the game
+    let max_depth = limits.max_depth.unwrap_or(usize::MAX).min(92);
+    let stop_helpers = AtomicBool::new(false);
+    let (mut result, depth, nodes, helper_nodes, probes, hits) = thread::scope(|scope| {
+        let helpers: Vec<_> = (1..limits.threads)
+            .map(|thread| {
+                let mut board = board.clone();
+                let stop_helpers = &stop_helpers;
+                scope.spawn(move || {
+                    let mut ctx = SearchContext::new(evaluator, deadline, None, tt);
+                    ctx.stop_flag = Some(stop_helpers);
+                    ctx.endgame_nodes = ENDGAME_PROBE_NODES;
+                    // Start every other helper a depth ahead, so they aren't all searching the
+                    // same positions at the same time
+                    let first_depth = 1 + thread % 2;
+                    iterative_deepening(&mut board, &mut ctx, first_depth, max_depth, None);
+                    (ctx.count, ctx.tt_probes, ctx.tt_hits)
                 })
-                .collect();
-            moves.sort_by(|(_, a), (_, b)| a.total_cmp(b));
-            moves
-                .into_iter()
-                .take_while(|(_, mm)| mm >= &alpha)
-                .min_by(|(_, a), (_, b)| a.total_cmp(b))
-                .unwrap_or((None, MMT::MIN))
-        }
-    } else if maxing {
-        board
-            .white_moves_boards()
-            .map(|(mov, board)| {
-                let (_, mm) = _minimax(&board, depth - 1, !maxing, alpha, beta, c, timeout);
-                alpha = alpha.max(mm);
-                (Some((mov, board)), mm)
             })
-            .take_while(|(_, mm)| mm <= &beta)
-            .max_by(|(_, a), (_, b)| a.total_cmp(b))
-            .unwrap_or((None, MMT::MAX))
+            .collect();
+        let mut ctx = SearchContext::new(evaluator, deadline, limits.max_nodes, tt);
+        ctx.stop_flag = limits.stop.as_deref();
+        ctx.endgame_nodes = ENDGAME_PROBE_NODES;
+        let mut board = board.clone();
+        let (result, depth) =
+            iterative_deepening(&mut board, &mut ctx, 1, max_depth, Some(start_time));
+        stop_helpers.store(true, Ordering::Relaxed);
+        let (mut helper_nodes, mut probes, mut hits) = (0, ctx.tt_probes, ctx.tt_hits);
+        for helper in helpers {
+            let (nodes, helper_probes, helper_hits) =
+                helper.join().expect("Search thread panicked");
+            helper_nodes += nodes;
+            probes += helper_probes;
+            hits += helper_hits;
+        }
+        (result, depth, ctx.count, helper_nodes, probes, hits)
+    });
+    debug!(
+        "Called minimax {nodes} times up to depth {depth} in {:?}",
+        start_time.elapsed()
+    );
+    if limits.threads > 1 {
+        debug!(
+            "Helper threads called minimax {helper_nodes} times, {:.0} nodes per second in total",
+            (nodes + helper_nodes) as f64 / start_time.elapsed().as_secs_f64()
+        );
+    }
+    debug!(
+        "Transposition table hit rate {:.1}%",
+        hits as f64 / probes.max(1) as f64 * 100.0
+    );
+    if result.0.is_none() {
+        // We were stopped before looking at a single move, just play anything
+        result.0 = board.legal_moves().next();
+    }
+    if !board.white_to_move {
+        result.1 = -result.1;
+    }
+    debug!("Evaluated as {:?}", result.1);
+    result
+}
+
+/// Plays the move from the [`Endgame`] solution if every amazon is walled off from the other
+/// player's and the solver can tell who wins, so there's nothing left to search for. The score is
+/// from white's point of view. The solver gets at most [`ENDGAME_MAX_NODES`], and stops early if
+/// the `limits` or `deadline` are reached.
+fn solve_endgame(
+    board: &Board,
+    limits: &SearchLimits,
+    deadline: Option<Instant>,
+    start_time: Instant,
+) -> Option<(Option<Move>, MMT)> {
+    let budget = Budget {
+        max_nodes: limits
+            .max_nodes
+            .map_or(ENDGAME_MAX_NODES, |max| max.min(ENDGAME_MAX_NODES)),
+        deadline,
+        stop: limits.stop.as_deref(),
+    };
+    let endgame = Endgame::solve(board, budget)?;
+    debug!(
+        "The board is partitioned: white can make {} moves and black {}, solved in {} nodes",
+        endgame.white, endgame.black, endgame.nodes
+    );
+    let (mover_wins, plies) = endgame_outcome(board, &endgame)?;
+    let score = if mover_wins { WIN } else { -WIN };
+    info!(
+        target: SEARCH_INFO_TARGET,
+        "depth {plies} score {score} nodes {} time {} pv {}",
+        endgame.nodes,
+        start_time.elapsed().as_millis(),
+        endgame.best_move.map(|mov| mov.notation()).unwrap_or_default()
+    );
+    let score = if board.white_to_move { score } else { -score };
+    Some((endgame.best_move, score))
+}
+
+/// Whether the player to move in `board` wins the solved `endgame`, and how many plies are left
+/// until the loser runs out of moves. `None` if the solver couldn't tell who wins, or didn't find
+/// a move for the player to move when they have one.
+fn endgame_outcome(board: &Board, endgame: &Endgame) -> Option<(bool, usize)> {
+    if endgame.best_move.is_none() && board.legal_moves().next().is_some() {
+        return None;
+    }
+    let mover_wins = (endgame.outcome()? == Outcome::WhiteWins) == board.white_to_move;
+    let (mover, other) = if board.white_to_move {
+        (endgame.white, endgame.black)
     } else {
-        board
-            .black_moves_boards()
-            .map(|(mov, board)| {
-                let (_, mm) = _minimax(&board, depth - 1, !maxing, alpha, beta, c, timeout);
-                beta = beta.min(mm);
-                (Some((mov, board)), mm)
-            })
-            .take_while(|(_, mm)| mm >= &alpha)
-            .min_by(|(_, a), (_, b)| a.total_cmp(b))
-            .unwrap_or((None, MMT::MIN))
-    }
-}
-
-pub fn minimax(board: &Board, is_white: bool) -> (Option<(Move, Board)>, MMT) {
-    // const POLL_INTERVAL: usize = 1; // how many cycles to go between timer checks
-    //                                 // TODO terminate better
-    let start_time = SystemTime::now();
-    let timeout = start_time + TIME_PER_TURN;
-    let mut count = 0;
-    let mut depth = 0;
-    let mut result = (None, HEURISTIC(board));
-    while SystemTime::now() < timeout {
-        eprintln!("  calculating depth {depth}");
-        let next_result = _minimax(
+        (endgame.black, endgame.white)
+    };
+    let plies = if mover_wins {
+        2 * other.upper + 1
+    } else {
+        2 * mover.upper
+    };
+    Some((mover_wins, plies))
+}
+
+/// Searches `board` to `first_depth`, then one depth deeper at a time until `max_depth` or until
+/// `ctx` is stopped. Returns the result of the deepest search that finished, and the depth it was
+/// searched to. After each depth, the search is logged if `start_time` is given.
+fn iterative_deepening(
+    board: &mut Board,
+    ctx: &mut SearchContext,
+    first_depth: usize,
+    max_depth: usize,
+    start_time: Option<Instant>,
+) -> ((Option<Move>, MMT), usize) {
+    let mut depth = first_depth;
+    let mut result = (None, evaluate(ctx.evaluator, board));
+    let mut pv = vec![];
+    let mut next_pv = vec![];
+    while depth <= max_depth && !ctx.should_stop() {
+        trace!("calculating depth {depth}");
+        let next_result = _minimax_in_place(
             board,
             depth,
-            is_white,
-            MMT::MIN,
-            MMT::MAX,
-            &mut count,
-            Some(timeout),
+            -MMT::INFINITY,
+            MMT::INFINITY,
+            ctx,
+            &pv,
+            &mut next_pv,
         );
-        if SystemTime::now() < timeout {
-            // IF we haven't timed out yet, then we know for sure we completely explored the tree
+        if !ctx.stopped {
+            // If we haven't been stopped, then we know for sure we completely explored the tree
             // up to the current depth. We don't want to use a partial calculation.
             result = next_result;
+            swap(&mut pv, &mut next_pv);
+        } else if result.0.is_none() {
+            // Unless we don't have anything better. The principal variation is searched first, so
+            // a partial search is never worse than the previous depth.
+            result = next_result;
+        }
+        if let (false, Some(start_time)) = (ctx.stopped, start_time) {
+            let pv: Vec<String> = pv.iter().map(Move::notation).collect();
+            info!(
+                target: SEARCH_INFO_TARGET,
+                "depth {depth} score {} nodes {} time {} pv {}",
+                result.1,
+                ctx.count,
+                start_time.elapsed().as_millis(),
+                pv.join(" ")
+            );
         }
-        eprintln!("  got {result:?}");
         depth += 1;
     }
-    eprintln!("Called minimax {count} times up to depth {depth}");
-    eprintln!("Evaluated as {:?}", result.1);
-    result
+    (result, depth - 1)
 }
 
+/// Logs a heat map of which player is closer to each square at debug level, along with how much
+/// of the board each amazon can reach and the regions the board is split into.
+#[allow(clippy::needless_range_loop)]
 pub fn print_h(board: &Board) {
+    if !log_enabled!(Level::Debug) {
+        return;
+    }
     let mut squares = [[0.0; 8]; 100];
     let mut seeds = vec![];
     let mut next_seeds = vec![]; // TODO capacity
@@ -862,16 +2082,15 @@ pub fn print_h(board: &Board) {
         })
         .collect();
     for i in (0..10).rev() {
-        for j in 0..10 {
-            let coord = (i * 10) + j;
-            eprint!("{:6.3} ", hs[coord]);
-        }
-        eprintln!();
-        eprintln!();
+        let row: Vec<String> = (0..10)
+            .map(|j| format!("{:6.3}", hs[(i * 10) + j]))
+            .collect();
+        debug!("heat map {:<2} {}", i + 1, row.join(" "));
     }
     for i in 0..8 {
-        eprintln!(
-            "{i}: {}",
+        debug!(
+            "reach of amazon on {}: {}",
+            PrettyCoord::from(board.pieces[i]),
             squares
                 .iter()
                 .map(|arr| arr[i])
@@ -879,5 +2098,727 @@ pub fn print_h(board: &Board) {
                 .sum::<f64>()
         );
     }
-    eprintln!("{}", hs.iter().sum::<f64>());
+    debug!("heat map total {}", hs.iter().sum::<f64>());
+    for region in board.regions() {
+        debug!("{region}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_legal_moves_are_legal() {
+        let board = Board::default();
+        for mov in board.white_moves() {
+            assert_eq!(board.check_move(&mov), Ok(()), "{mov}");
+        }
+        let board = Board {
+            white_to_move: false,
+            ..board
+        };
+        for mov in board.black_moves() {
+            assert_eq!(board.check_move(&mov), Ok(()), "{mov}");
+        }
+    }
+
+    #[test]
+    fn test_illegal_moves() {
+        let mut board = Board::default();
+        assert_eq!(
+            board.try_apply_move(&Move(c!(a5), c!(a6), c!(a5))),
+            Err(MoveError::NoPiece(c!(a5)))
+        );
+        assert_eq!(
+            board.try_apply_move(&Move(c!(a7), c!(a6), c!(a5))),
+            Err(MoveError::WrongTurn(c!(a7)))
+        );
+        // Knight hop
+        assert_eq!(
+            board.try_apply_move(&Move(c!(a4), c!(b6), c!(b7))),
+            Err(MoveError::Unreachable(c!(a4), c!(b6)))
+        );
+        // Through the black amazon on a7
+        assert_eq!(
+            board.try_apply_move(&Move(c!(a4), c!(a8), c!(b8))),
+            Err(MoveError::Unreachable(c!(a4), c!(a8)))
+        );
+        // Onto the black amazon on a7
+        assert_eq!(
+            board.try_apply_move(&Move(c!(a4), c!(a6), c!(a7))),
+            Err(MoveError::ArrowUnreachable(c!(a6), c!(a7)))
+        );
+        assert_eq!(
+            board.try_apply_move(&Move(c!(a4), c!(a5), 100)),
+            Err(MoveError::OutOfBounds(100))
+        );
+        // Nothing was applied
+        assert_eq!(board.tiles, Board::default().tiles);
+    }
+
+    #[test]
+    fn test_arrow_through_vacated_square() {
+        let mut board = Board::default();
+        assert_eq!(board.try_apply_move(&Move(c!(a4), c!(a5), c!(a3))), Ok(()));
+        let mut board = Board::default();
+        assert_eq!(board.try_apply_move(&Move(c!(a4), c!(a5), c!(a4))), Ok(()));
+        assert!(board.tiles[c!(a4)] == TileState::Arrow);
+        assert!(board.tiles[c!(a5)] == TileState::White);
+    }
+
+    #[test]
+    fn test_legal_destinations_and_arrow_targets() {
+        for board in random_positions(5).into_iter().step_by(7) {
+            let mut moves = vec![];
+            for &piece in board.pieces.iter() {
+                for to in board.legal_destinations(piece) {
+                    for arrow in board.legal_arrow_targets(piece, to) {
+                        moves.push(Move(piece, to, arrow));
+                    }
+                }
+            }
+            let mut legal_moves: Vec<Move> = board.legal_moves().collect();
+            moves.sort_by_key(|Move(piece, to, arrow)| (*piece, *to, *arrow));
+            legal_moves.sort_by_key(|Move(piece, to, arrow)| (*piece, *to, *arrow));
+            assert_eq!(moves, legal_moves);
+        }
+        let board = Board::default();
+        // Black's amazons can't move on white's turn, and empty squares have nothing to move
+        assert_eq!(board.legal_destinations(c!(a7)).count(), 0);
+        assert_eq!(board.legal_destinations(c!(e5)).count(), 0);
+        assert_eq!(board.legal_destinations(100).count(), 0);
+        assert_eq!(board.legal_arrow_targets(c!(a4), c!(b6)).count(), 0);
+        assert!(board
+            .legal_arrow_targets(c!(a4), c!(a5))
+            .any(|arrow| arrow == c!(a4)));
+    }
+
+    #[test]
+    fn test_fen_round_trip() {
+        let start = "3B2B3/10/10/B8B/10/10/W8W/10/10/3W2W3 w 0";
+        assert_eq!(Board::default().to_fen(), start);
+        assert_eq!(Board::from_fen(start), Ok(Board::default()));
+        // Equality ignores the order of the amazons, but the search does not
+        assert_eq!(
+            Board::from_fen(&Board::default().to_fen()).unwrap().pieces,
+            Board::default().pieces
+        );
+        for board in random_positions(6) {
+            let fen = board.to_fen();
+            let parsed: Board = fen.parse().unwrap();
+            assert_eq!(parsed, board);
+            assert_eq!(parsed.hash, board.hash);
+            assert_eq!(parsed.collisions, board.collisions);
+            assert_eq!(parsed.legal_moves().count(), board.legal_moves().count());
+            assert_eq!(parsed.to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn test_fen_errors() {
+        let start = Board::default().to_fen();
+        assert!(matches!(
+            Board::from_fen("3B2B3/10 w"),
+            Err(FenError::Fields(_))
+        ));
+        assert_eq!(
+            Board::from_fen(&start.replacen("10/", "", 1)),
+            Err(FenError::RowCount(9))
+        );
+        assert_eq!(
+            Board::from_fen(&start.replacen("B8B", "B8Q", 1)),
+            Err(FenError::Character { row: 7, found: 'Q' })
+        );
+        assert_eq!(
+            Board::from_fen(&start.replacen("3B2B3", "3B2B4", 1)),
+            Err(FenError::RowLength {
+                row: 10,
+                squares: 11
+            })
+        );
+        assert_eq!(
+            Board::from_fen(&start.replacen("W8W", "W8x", 1)),
+            Err(FenError::Amazons { white: 3, black: 4 })
+        );
+        assert_eq!(
+            Board::from_fen(&start.replace(" w ", " white ")),
+            Err(FenError::SideToMove("white".to_string()))
+        );
+        assert_eq!(
+            Board::from_fen(&start.replace(" 0", " -1")),
+            Err(FenError::Ply("-1".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_side_to_move() {
+        let mut board = Board::default();
+        assert!(board.white_to_move);
+        assert_eq!(board.ply, 0);
+        assert_eq!(board.outcome(), Outcome::Ongoing);
+        board.try_apply_move(&Move(c!(a4), c!(a5), c!(a6))).unwrap();
+        assert!(!board.white_to_move);
+        assert_eq!(board.ply, 1);
+        assert_eq!(
+            board.try_apply_move(&Move(c!(d1), c!(d2), c!(d3))),
+            Err(MoveError::WrongTurn(c!(d1)))
+        );
+        board.try_apply_move(&Move(c!(a7), c!(b7), c!(b6))).unwrap();
+        assert!(board.white_to_move);
+        assert_eq!(board.ply, 2);
+    }
+
+    #[test]
+    fn test_outcome() {
+        let mut board = Board::default();
+        // Wall in every white amazon
+        for piece in board.pieces.into_iter().take(4) {
+            for mov in board.reachable_squares(&piece).collect::<Vec<_>>() {
+                board.tiles[mov] = TileState::Arrow;
+                board.toggle_collision(mov);
+            }
+        }
+        assert!(!board.has_legal_moves());
+        assert_eq!(board.legal_moves().count(), 0);
+        assert_eq!(board.outcome(), Outcome::BlackWins);
+        board.white_to_move = false;
+        assert_eq!(board.outcome(), Outcome::Ongoing);
+    }
+
+    #[test]
+    fn test_unmake_move() {
+        let mut board = Board::default();
+        board.apply_move(&Move(c!(a4), c!(a5), c!(a4)));
+        for mov in board.legal_moves().collect::<Vec<_>>() {
+            let before = board.clone();
+            let undo = board.apply_move(&mov);
+            board.unmake_move(undo);
+            assert_eq!(board.pieces, before.pieces, "{mov}");
+            assert_eq!(board.tiles, before.tiles, "{mov}");
+            assert_eq!(board.collisions, before.collisions, "{mov}");
+            assert_eq!(board.white_to_move, before.white_to_move, "{mov}");
+            assert_eq!(board.ply, before.ply, "{mov}");
+            assert_eq!(board.hash, before.hash, "{mov}");
+        }
+    }
+
+    /// Minimax without any pruning, to check the faster searches against.
+    fn plain_minimax(board: &Board, depth: usize) -> MMT {
+        if depth == 0 {
+            return better_reachable_heuristic(board);
+        }
+        let scores = board
+            .legal_moves_boards()
+            .map(|(_, board)| plain_minimax(&board, depth - 1));
+        if board.white_to_move {
+            scores.fold(-(WIN + depth as MMT), MMT::max)
+        } else {
+            scores.fold(WIN + depth as MMT, MMT::min)
+        }
+    }
+
+    /// Positions near the end of a game, which are small enough to search without pruning.
+    fn small_positions() -> Vec<Board> {
+        (0..4)
+            .map(|seed| {
+                let positions = random_positions(seed);
+                positions[positions.len() - 8].clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_in_place_search_matches_clone_search() {
+        for mut board in small_positions() {
+            for depth in 0..=3 {
+                let mut cloned_count = 0;
+                let (cloned_mov, cloned_mm) = _minimax(
+                    &board,
+                    depth,
+                    -MMT::INFINITY,
+                    MMT::INFINITY,
+                    &mut cloned_count,
+                    None,
+                    &BetterReachableHeuristic,
+                );
+                let tt = TranspositionTable::new(0);
+                let mut ctx = SearchContext::new(&BetterReachableHeuristic, None, None, &tt);
+                let (mov, mm) = _minimax_in_place(
+                    &mut board,
+                    depth,
+                    -MMT::INFINITY,
+                    MMT::INFINITY,
+                    &mut ctx,
+                    &[],
+                    &mut vec![],
+                );
+                assert_eq!(cloned_mov, mov);
+                assert_eq!(cloned_mm, mm);
+                assert_eq!(cloned_count, ctx.count);
+            }
+        }
+    }
+
+    #[test]
+    fn test_alpha_beta_matches_plain_minimax() {
+        for mut board in small_positions() {
+            for depth in 1..=3 {
+                let expected = plain_minimax(&board, depth);
+                let sign = if board.white_to_move { 1.0 } else { -1.0 };
+                let (_, mm) = _minimax(
+                    &board,
+                    depth,
+                    -MMT::INFINITY,
+                    MMT::INFINITY,
+                    &mut 0,
+                    None,
+                    &BetterReachableHeuristic,
+                );
+                assert_eq!(expected, sign * mm, "depth {depth}\n{board}");
+                let tt = TranspositionTable::new(1 << 12);
+                let mut ctx = SearchContext::new(&BetterReachableHeuristic, None, None, &tt);
+                let (_, mm) = _minimax_in_place(
+                    &mut board,
+                    depth,
+                    -MMT::INFINITY,
+                    MMT::INFINITY,
+                    &mut ctx,
+                    &[],
+                    &mut vec![],
+                );
+                assert_eq!(expected, sign * mm, "depth {depth}\n{board}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_principal_variation() {
+        for mut board in small_positions() {
+            let tt = TranspositionTable::new(1 << 12);
+            let mut ctx = SearchContext::new(&BetterReachableHeuristic, None, None, &tt);
+            let mut pv = vec![];
+            let mut next_pv = vec![];
+            for depth in 1..=3 {
+                let (mov, mm) = _minimax_in_place(
+                    &mut board,
+                    depth,
+                    -MMT::INFINITY,
+                    MMT::INFINITY,
+                    &mut ctx,
+                    &pv,
+                    &mut next_pv,
+                );
+                assert_eq!(next_pv.first().copied(), mov);
+                swap(&mut pv, &mut next_pv);
+                // Playing out the principal variation reaches the position that decided the score
+                let mut leaf = board.clone();
+                for mov in pv.iter() {
+                    leaf.try_apply_move(mov).unwrap();
+                }
+                if pv.len() == depth {
+                    let sign = if pv.len() % 2 == 0 { 1.0 } else { -1.0 };
+                    assert_eq!(
+                        mm,
+                        sign * evaluate(&BetterReachableHeuristic, &leaf),
+                        "depth {depth}\n{board}"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Plays random moves from the starting position, returning every position along the way.
+    fn random_positions(seed: u64) -> Vec<Board> {
+        let mut player = RandomPlayer::new(seed);
+        let mut board = Board::default();
+        let mut boards = vec![board.clone()];
+        while let Some(mov) = player.choose_move(&board) {
+            board.apply_move(&mov);
+            boards.push(board.clone());
+        }
+        boards
+    }
+
+    #[test]
+    fn test_random_player() {
+        let boards = random_positions(3);
+        assert_eq!(random_positions(3), boards);
+        assert_ne!(random_positions(4), boards);
+        // Each move is for the side to move, and the game is played out to the end
+        let mut player = RandomPlayer::new(3);
+        for board in &boards {
+            match player.choose_move(board) {
+                Some(mov) => assert!(board.is_legal(&mov), "{mov}\n{board}"),
+                None => assert_ne!(board.outcome(), Outcome::Ongoing),
+            }
+        }
+    }
+
+    #[test]
+    fn test_reachable_mask_parity() {
+        for seed in 0..5 {
+            for board in random_positions(seed) {
+                for coord in 0..100 {
+                    let mut expected: Vec<Coord> = board.reachable_squares(&coord).collect();
+                    expected.sort();
+                    let actual: Vec<Coord> = mask_coords(board.reachable_mask(&coord)).collect();
+                    assert_eq!(expected, actual, "{}\n{board}", PrettyCoord::from(coord));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_moves_parity() {
+        let board = Board::default();
+        let mut moves = vec![];
+        board.generate_moves(0..4, &mut moves);
+        assert_eq!(moves.len(), 2176);
+        assert_eq!(board.white_moves().count(), 2176);
+        for seed in 0..5 {
+            for board in random_positions(seed) {
+                for piece_idx in 0..8 {
+                    let expected: Vec<Move> = PieceMoveIterator::new(&board, piece_idx).collect();
+                    let mut actual = vec![];
+                    board.generate_piece_moves(piece_idx, &mut actual);
+                    assert_eq!(expected, actual, "{piece_idx}\n{board}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_incremental_hash() {
+        for seed in 0..10 {
+            let positions = random_positions(seed);
+            for board in positions.iter() {
+                assert_eq!(board.hash, board.compute_hash(), "\n{board}");
+            }
+            // Every position in a game is different
+            let mut hashes: Vec<u64> = positions.iter().map(|board| board.hash).collect();
+            hashes.sort();
+            hashes.dedup();
+            assert_eq!(hashes.len(), positions.len());
+        }
+    }
+
+    #[test]
+    fn test_transposition_hash() {
+        let mut a = Board::default();
+        a.apply_move(&Move(c!(a4), c!(a5), c!(a6)));
+        a.apply_move(&Move(c!(a7), c!(b7), c!(b6)));
+        a.apply_move(&Move(c!(j4), c!(j5), c!(j6)));
+        let mut b = Board::default();
+        b.apply_move(&Move(c!(j4), c!(j5), c!(j6)));
+        b.apply_move(&Move(c!(a7), c!(b7), c!(b6)));
+        b.apply_move(&Move(c!(a4), c!(a5), c!(a6)));
+        assert_eq!(a.hash, b.hash);
+        assert_ne!(a.hash, Board::default().hash);
+    }
+
+    #[test]
+    fn test_transposition_table_search() {
+        let mut board = random_positions(0)[60].clone();
+        let (_, expected) = _minimax(
+            &board,
+            3,
+            -MMT::INFINITY,
+            MMT::INFINITY,
+            &mut 0,
+            None,
+            &BetterReachableHeuristic,
+        );
+        let tt = TranspositionTable::new(1 << 16);
+        let mut ctx = SearchContext::new(&BetterReachableHeuristic, None, None, &tt);
+        let mut result = (None, 0.0);
+        for depth in 1..=3 {
+            result = _minimax_in_place(
+                &mut board,
+                depth,
+                -MMT::INFINITY,
+                MMT::INFINITY,
+                &mut ctx,
+                &[],
+                &mut vec![],
+            );
+        }
+        assert_eq!(result.1, expected);
+        assert!(ctx.tt_hits > 0 && ctx.tt_hits <= ctx.tt_probes);
+        assert!(board.is_legal(&result.0.unwrap()));
+    }
+
+    #[test]
+    fn test_search_limits() {
+        let board = Board::default();
+        let mut tt = TranspositionTable::new(1 << 12);
+        let limits = SearchLimits {
+            max_depth: Some(1),
+            ..SearchLimits::infinite()
+        };
+        let (mov, _) = minimax(&board, &mut tt, &limits, &BetterReachableHeuristic);
+        assert!(board.is_legal(&mov.unwrap()));
+
+        let limits = SearchLimits {
+            max_nodes: Some(100),
+            ..SearchLimits::infinite()
+        };
+        let (mov, _) = minimax(&board, &mut tt, &limits, &BetterReachableHeuristic);
+        assert!(board.is_legal(&mov.unwrap()));
+
+        let move_time = Duration::from_millis(50);
+        let limits = SearchLimits {
+            move_time: Some(move_time),
+            ..SearchLimits::infinite()
+        };
+        let start_time = Instant::now();
+        let (mov, _) = minimax(&board, &mut tt, &limits, &BetterReachableHeuristic);
+        assert!(board.is_legal(&mov.unwrap()));
+        let overshoot = start_time.elapsed().saturating_sub(move_time);
+        // The clock is checked every few hundred nodes, which takes a fraction of a millisecond in
+        // a release build. Debug builds are far slower, so only check that the deadline stopped
+        // the search at all, since otherwise it would search to the end of the game.
+        if cfg!(debug_assertions) {
+            assert!(overshoot < Duration::from_secs(1), "{overshoot:?}");
+        } else {
+            assert!(overshoot < Duration::from_millis(20), "{overshoot:?}");
+        }
+    }
+
+    #[test]
+    fn test_threads() {
+        let board = random_positions(1)[20].clone();
+        let search = |threads| {
+            let mut tt = TranspositionTable::new(1 << 12);
+            let limits = SearchLimits {
+                max_depth: Some(2),
+                threads,
+                ..SearchLimits::infinite()
+            };
+            minimax(&board, &mut tt, &limits, &BetterReachableHeuristic)
+        };
+        // A single thread always searches the same way
+        let single = search(1);
+        assert_eq!(search(1), single);
+        let (mov, _) = search(4);
+        assert!(board.is_legal(&mov.unwrap()));
+    }
+
+    #[test]
+    fn test_time_budget() {
+        let board = Board::default();
+        let limits = SearchLimits {
+            move_time: None,
+            remaining: Some(Duration::from_secs(60)),
+            increment: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let budget = limits.time_budget(&board).unwrap();
+        assert!(budget > Duration::from_secs(1) && budget < Duration::from_secs(10));
+        // The fixed move time takes priority when it is shorter
+        let limits = SearchLimits {
+            move_time: Some(Duration::from_millis(100)),
+            ..limits
+        };
+        assert_eq!(limits.time_budget(&board), Some(Duration::from_millis(100)));
+        // Never spend the whole clock
+        let limits = SearchLimits {
+            move_time: None,
+            remaining: Some(Duration::from_millis(100)),
+            ..limits
+        };
+        assert!(limits.time_budget(&board).unwrap() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_minimax_solves_endgame() {
+        // White has 3 moves left to black's 4, so loses whoever is to move
+        for side in ["w", "b"] {
+            let board: Board = format!(
+                "BxxxxB1BxB/xxxxx3xx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/\
+                 xxxxxxxxxx/xxx2xxxxx/WxxW1WxxxW {side} 60"
+            )
+            .parse()
+            .unwrap();
+            let mut tt = TranspositionTable::new(0);
+            let (mov, score) = minimax(
+                &board,
+                &mut tt,
+                &SearchLimits::default(),
+                &BetterReachableHeuristic,
+            );
+            assert_eq!(score, -WIN);
+            // Black fills their region in order, and white can't do better than to fill theirs
+            let mut after = board.clone();
+            after.try_apply_move(&mov.unwrap()).unwrap();
+            let endgame = Endgame::solve(&after, Budget::nodes(100_000)).unwrap();
+            if board.white_to_move {
+                assert_eq!(endgame.white, endgame::MoveCount::exact(2));
+            } else {
+                assert_eq!(endgame.black, endgame::MoveCount::exact(3));
+            }
+        }
+    }
+
+    #[test]
+    fn test_minimax_endgame_always_moves() {
+        // Black's regions are big enough to use up the solver's nodes, which mustn't leave white
+        // without a move
+        let board: Board = "x1xB2xxxW/2xxx1xWxx/1x1xxxxxW1/xx2xx1x1x/2xx1xWxxx/B2x1xxxx1/\
+                            1xxxx1xBx1/x1x3xx1x/2x1xx1x2/x2B2xxxx w 54"
+            .parse()
+            .unwrap();
+        let mut tt = TranspositionTable::new(0);
+        let limits = SearchLimits {
+            move_time: None,
+            max_depth: Some(1),
+            ..SearchLimits::default()
+        };
+        let (mov, _) = minimax(&board, &mut tt, &limits, &BetterReachableHeuristic);
+        assert!(board.is_legal(&mov.expect("White has moves")));
+
+        // The solver's budget comes out of the search's
+        let limits = SearchLimits {
+            max_nodes: Some(10),
+            ..limits
+        };
+        let (mov, _) = minimax(&board, &mut tt, &limits, &BetterReachableHeuristic);
+        assert!(board.is_legal(&mov.expect("White has moves")));
+    }
+
+    #[test]
+    fn test_search_probes_endgame() {
+        // White has 3 moves left to black's 4, so white runs out first, 6 plies from now
+        let mut board: Board = "BxxxxB1BxB/xxxxx3xx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/\
+                                xxxxxxxxxx/xxxxxxxxxx/xxx2xxxxx/WxxW1WxxxW w 60"
+            .parse()
+            .unwrap();
+        let tt = TranspositionTable::new(1 << 10);
+        let mut ctx = SearchContext::new(&BetterReachableHeuristic, None, None, &tt);
+        ctx.endgame_nodes = ENDGAME_PROBE_NODES;
+        let mut pv = vec![];
+        let (mov, mm) = _minimax_in_place(
+            &mut board,
+            1,
+            -MMT::INFINITY,
+            MMT::INFINITY,
+            &mut ctx,
+            &[],
+            &mut pv,
+        );
+        assert!(board.is_legal(&mov.unwrap()));
+        assert_eq!(pv, [mov.unwrap()]);
+        assert_eq!(mm, -(WIN + 1.0 - 6.0));
+
+        // The solved position was stored, so searching it again doesn't solve it again
+        let count = ctx.count;
+        let again = _minimax_in_place(
+            &mut board,
+            1,
+            -MMT::INFINITY,
+            MMT::INFINITY,
+            &mut ctx,
+            &[],
+            &mut pv,
+        );
+        assert_eq!(again, (mov, mm));
+        assert_eq!(ctx.count, count + 1);
+        assert_eq!(ctx.tt_hits, 1);
+    }
+
+    #[test]
+    fn test_territory_heuristic() {
+        // White owns 29 squares below the wall and black 37 above it
+        let board: Board = "3B2B3/10/10/B8B/xxxxx1xxxx/xxxxxxxxxx/Wx7W/xx8/8xx/3W2W1x1 w 30"
+            .parse()
+            .unwrap();
+        assert_eq!(territory_heuristic(&board), -8.0);
+        // With a gap at e5 the halves are one contested region, and each square goes to whoever
+        // can get there first. White reaches e5 first and both reach f6 in two moves.
+        let board: Board = "3B2B3/10/10/B8B/xxxxx1xxxx/xxxx1xxxxx/Wx7W/xx8/8xx/3W2W1x1 w 30"
+            .parse()
+            .unwrap();
+        assert_eq!(territory_heuristic(&board), -7.0);
+        let distances = queen_distances(&board, 0..4);
+        assert_eq!(distances[c!(e4)], 1);
+        assert_eq!(distances[c!(e5)], 2);
+        assert_eq!(distances[c!(f6)], 2);
+        assert_eq!(distances[c!(j1)], u8::MAX);
+    }
+
+    #[test]
+    fn test_evaluator_by_name() {
+        for evaluator in EVALUATORS {
+            let found = evaluator_by_name(evaluator.name()).unwrap();
+            assert_eq!(found.name(), evaluator.name());
+            // The starting position is symmetrical
+            assert_eq!(found.evaluate(&Board::default()), 0.0);
+        }
+        assert!(evaluator_by_name("nonsense").is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("a1".parse(), Ok(PrettyCoord(Dim::A, Dim::A)));
+        assert_eq!("j10".parse(), Ok(PrettyCoord(Dim::J, Dim::J)));
+        assert_eq!(
+            "k1".parse::<PrettyCoord>(),
+            Err(ParseError::Column("k1".to_string()))
+        );
+        assert_eq!(
+            "".parse::<PrettyCoord>(),
+            Err(ParseError::Column("".to_string()))
+        );
+        assert_eq!(
+            "a11".parse::<PrettyCoord>(),
+            Err(ParseError::Row("a11".to_string()))
+        );
+        assert_eq!(
+            "a0".parse::<PrettyCoord>(),
+            Err(ParseError::Row("a0".to_string()))
+        );
+        assert_eq!(" a4-a5/a6\n".parse(), Ok(Move(c!(a4), c!(a5), c!(a6))));
+        assert_eq!(
+            "a4a5/a6".parse::<Move>(),
+            Err(ParseError::Format("a4a5/a6".to_string()))
+        );
+        assert_eq!(
+            "a4-a5".parse::<Move>(),
+            Err(ParseError::Format("a4-a5".to_string()))
+        );
+        let error = "a4-z5/a6".parse::<Move>().unwrap_err();
+        assert_eq!(
+            error,
+            ParseError::InMove(
+                MovePart::Destination,
+                Box::new(ParseError::Column("z5".to_string()))
+            )
+        );
+        assert_eq!(
+            error.to_string(),
+            "invalid column in coordinate \"z5\" for the destination"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_fen_never_panics(s in "[WBx0-9/ wb]{0,80}") {
+            let _ = Board::from_fen(&s);
+        }
+
+        #[test]
+        fn test_parse_never_panics(s in "\\PC*") {
+            let _ = s.parse::<PrettyCoord>();
+            let _ = s.parse::<Move>();
+        }
+
+        #[test]
+        fn test_parse_move_like_never_panics(s in "[a-k]?[0-9]{0,3}[-/]?[a-k]?[0-9]{0,3}[-/]?[a-k]?[0-9]{0,3}") {
+            if let Ok(Move(piece, mov, arrow)) = s.parse::<Move>() {
+                prop_assert!(piece < 100 && mov < 100 && arrow < 100);
+                prop_assert_eq!(Move(piece, mov, arrow).notation(), s);
+            }
+        }
+    }
 }
@@ -1,54 +1,557 @@
-use amazons_core::*;
-use clap::Parser;
-use std::{io, thread, time::Duration};
+use amazons_core::{
+    protocol::{Command, Response},
+    *,
+};
+use clap::{Parser, ValueEnum};
+use log::{debug, info, trace, warn, LevelFilter, Log, Metadata, Record};
+use std::{
+    io,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Speak the original protocol, where moves are written and read as bare lines instead of
+    /// using the commands described in `amazons_core::protocol`
+    #[arg(long)]
+    legacy: bool,
+    /// Play black instead of white. Only used with `--legacy`
     #[arg(long)]
     black: bool,
+    /// Size of the transposition table used by minimax, in megabytes
+    #[arg(long, default_value_t = 64)]
+    hash: usize,
+    /// Fixed time to spend on each move, in milliseconds. Defaults to 10 seconds if no other
+    /// limit is given
+    #[arg(long)]
+    move_time: Option<u64>,
+    /// Total time on the engine's game clock, in milliseconds. Only used with `--legacy`, otherwise
+    /// the clocks are sent with every `go`
+    #[arg(long)]
+    time: Option<u64>,
+    /// Time added to the engine's game clock after each of its moves, in milliseconds. Only used
+    /// with `--legacy`
+    #[arg(long, default_value_t = 0)]
+    increment: u64,
+    /// Maximum depth to search to
+    #[arg(long)]
+    depth: Option<usize>,
+    /// Maximum number of positions to search per move
+    #[arg(long)]
+    nodes: Option<usize>,
+    /// How many threads the minimax search uses. The search is deterministic with one thread
+    #[arg(long, default_value_t = 1, value_parser = parse_threads)]
+    threads: usize,
+    /// The search algorithm to play with
+    #[arg(long, value_enum, default_value_t = Algorithm::Minimax)]
+    search: Algorithm,
+    /// How much `--search mcts` favours rarely visited moves over ones that have scored well
+    #[arg(long, default_value_t = MctsConfig::default().exploration)]
+    exploration: f64,
+    /// How `--search mcts` estimates the value of a position: `random` plays random moves to the
+    /// end of the game, `heuristic` plays `--cutoff-depth` random moves and then evaluates with
+    /// the better-reachable heuristic
+    #[arg(long, default_value_t = MctsConfig::default().playout)]
+    playout: Playout,
+    /// How many random moves a heuristic playout plays before evaluating the position
+    #[arg(long, default_value_t = MctsConfig::default().cutoff_depth)]
+    cutoff_depth: usize,
+    /// Seed for the random numbers used by `--search mcts` or `random`, so that a game can be replayed
+    /// exactly. A new seed is picked for every game by default, and shown with `--log debug`
+    #[arg(long)]
+    seed: Option<u64>,
+    /// The evaluation function to use at the leaves of the minimax search
+    #[arg(long, default_value = EVALUATORS[0].name(), value_parser = parse_evaluator)]
+    eval: &'static dyn Evaluator,
+    /// The position to start from instead of the usual one, in the format from `Board::to_fen`
+    #[arg(long, value_parser = Board::from_fen)]
+    fen: Option<Board>,
+    /// Append each game to this file when it ends, in the format from `amazons_core::record`.
+    /// Without `--legacy` a game ends at `newgame` or `quit`
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// How much to log. Logs are sent as `info` lines, or to stderr with `--legacy`
+    #[arg(long, value_enum, env = "AMAZONS_LOG", default_value_t = LogLevel::Off)]
+    log: LogLevel,
+}
+
+/// The most threads the search can be asked to use.
+const MAX_THREADS: usize = 256;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Algorithm {
+    /// Alpha-beta search with iterative deepening
+    Minimax,
+    /// Monte Carlo tree search, which keeps its tree between moves
+    Mcts,
+    /// A random legal move, instantly. Useful as an opponent when testing other engines
+    Random,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum LogLevel {
+    Off,
+    /// Warnings and a summary of each depth searched
+    Info,
+    /// Also search statistics, the board and a heat map of who controls which squares
+    Debug,
+    /// Everything
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Sends log records to the GUI as `info` lines. With the legacy protocol stdout is reserved for
+/// moves, so they go to stderr instead.
+struct Logger {
+    legacy: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        // Filtering is done by log::set_max_level
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let level = record.level().as_str().to_lowercase();
+        if self.legacy {
+            eprintln!("{level} {}", record.args());
+        } else if record.target() == SEARCH_INFO_TARGET {
+            respond(Response::Info(record.args().to_string()));
+        } else {
+            respond(Response::Info(format!("string {level} {}", record.args())));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn parse_evaluator(name: &str) -> Result<&'static dyn Evaluator, String> {
+    evaluator_by_name(name).ok_or_else(|| {
+        let names: Vec<&str> = EVALUATORS
+            .iter()
+            .map(|evaluator| evaluator.name())
+            .collect();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+fn parse_threads(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(threads @ 1..=MAX_THREADS) => Ok(threads),
+        _ => Err(format!("expected a number from 1 to {MAX_THREADS}")),
+    }
+}
+
+impl Args {
+    /// How long to search for when neither the command line nor `go` says.
+    fn default_move_time(&self) -> Option<Duration> {
+        self.move_time
+            .map(Duration::from_millis)
+            .or(SearchLimits::default().move_time)
+    }
+
+    fn search_limits(&self) -> SearchLimits {
+        let no_limits = self.time.is_none() && self.depth.is_none() && self.nodes.is_none();
+        SearchLimits {
+            move_time: match self.move_time {
+                Some(move_time) => Some(Duration::from_millis(move_time)),
+                None if no_limits => SearchLimits::default().move_time,
+                None => None,
+            },
+            remaining: self.time.map(Duration::from_millis),
+            increment: Duration::from_millis(self.increment),
+            max_depth: self.depth,
+            max_nodes: self.nodes,
+            stop: None,
+            threads: self.threads,
+        }
+    }
+
+    /// An empty record of a game from the starting position.
+    fn new_record(&self) -> GameRecord {
+        let mut record = GameRecord::new(self.fen.clone().unwrap_or_default());
+        if let Some(time) = self.time {
+            record.set_header("TimeControl", &format!("{time}+{}", self.increment));
+        } else if let Some(move_time) = self.move_time {
+            record.set_header("TimeControl", &format!("{move_time}/move"));
+        }
+        record
+    }
+
+    /// Appends the record to the `--record` file, if there is one.
+    fn save_record(&self, record: &mut GameRecord) {
+        let Some(path) = &self.record else {
+            return;
+        };
+        if let Err(err) = record.set_result_from_board() {
+            warn!("Not saving the game: {err}");
+            return;
+        }
+        match record.append_to(path) {
+            Ok(()) => info!("Saved the game to {}", path.display()),
+            Err(err) => warn!("Failed to save the game to {}: {err}", path.display()),
+        }
+    }
+}
+
+/// The search to play with, along with what it remembers from one move to the next.
+enum Searcher {
+    Minimax(TranspositionTable),
+    Mcts(Box<Mcts>),
+    Random(Box<RandomPlayer>),
+}
+
+impl Searcher {
+    fn new(args: &Args) -> Self {
+        match args.search {
+            Algorithm::Minimax => Searcher::Minimax(TranspositionTable::with_size_mb(args.hash)),
+            Algorithm::Mcts => Searcher::Mcts(Box::new(Mcts::new(MctsConfig {
+                exploration: args.exploration,
+                playout: args.playout,
+                cutoff_depth: args.cutoff_depth,
+                seed: args.seed,
+            }))),
+            Algorithm::Random => Searcher::Random(Box::new(RandomPlayer::new(
+                args.seed.unwrap_or_else(rand::random),
+            ))),
+        }
+    }
+
+    fn search(
+        &mut self,
+        board: &Board,
+        limits: &SearchLimits,
+        evaluator: &dyn Evaluator,
+    ) -> (Option<Move>, MMT) {
+        match self {
+            Searcher::Minimax(tt) => minimax(board, tt, limits, evaluator),
+            Searcher::Mcts(mcts) => mcts.search(board, limits),
+            Searcher::Random(player) => (player.choose_move(board), 0.0),
+        }
+    }
+
+    /// Forgets everything from earlier searches.
+    fn clear(&mut self) {
+        match self {
+            Searcher::Minimax(tt) => tt.clear(),
+            Searcher::Mcts(mcts) => mcts.clear(),
+            // Nothing is remembered, and reseeding would replay the same game
+            Searcher::Random(_) => {}
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
-    let mut input = String::new();
-    let stdin = io::stdin();
-    let mut board = Board::default();
-    loop {
-        if !args.black {
-            if let (Some((mov, new_board)), _h) = minimax(&board, true) {
-                println!("{}", mov.notation());
-                board = new_board;
-            } else {
-                println!("Black wins");
+    log::set_boxed_logger(Box::new(Logger {
+        legacy: args.legacy,
+    }))
+    .expect("No other logger has been set");
+    log::set_max_level(args.log.into());
+    if args.legacy {
+        run_legacy(&args);
+    } else {
+        Engine::new(&args).run();
+    }
+}
+
+/// A search running on another thread. It prints `bestmove` when it's done.
+struct Search {
+    stop: Arc<AtomicBool>,
+    // Hands back the searcher and the move it chose once the search is over
+    handle: JoinHandle<(Searcher, Option<Move>)>,
+}
+
+/// The engine's side of the protocol in [`amazons_core::protocol`].
+struct Engine<'a> {
+    args: &'a Args,
+    board: Board,
+    // Only `None` while a search is using it
+    searcher: Option<Searcher>,
+    search: Option<Search>,
+    evaluator: &'static dyn Evaluator,
+    threads: usize,
+    // The moves of the latest `position`, saved when the game ends
+    record: GameRecord,
+    // The move the latest search chose from `board`, which the GUI hasn't sent back yet
+    our_move: Option<Move>,
+    // Whether we've been playing white or black this game, once we've been asked to move
+    playing_white: Option<bool>,
+}
+
+impl<'a> Engine<'a> {
+    fn new(args: &'a Args) -> Self {
+        Self {
+            args,
+            board: args.fen.clone().unwrap_or_default(),
+            searcher: Some(Searcher::new(args)),
+            search: None,
+            evaluator: args.eval,
+            threads: args.threads,
+            record: args.new_record(),
+            our_move: None,
+            playing_white: None,
+        }
+    }
+
+    /// Handles commands from stdin until told to quit or stdin is closed.
+    fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut input = String::new();
+        loop {
+            input.clear();
+            if stdin.read_line(&mut input).expect("Error reading input") == 0 {
                 break;
             }
-        } else {
-            input.clear();
-            stdin.read_line(&mut input).expect("Error reading input");
-            board.apply_move(&Move::parse_notation(&input).expect("Failed to parse notation"));
+            if input.trim().is_empty() {
+                continue;
+            }
+            match input.parse() {
+                Ok(Command::Quit) => break,
+                Ok(command) => self.handle(command),
+                Err(err) => warn!("Ignoring {:?}: {err}", input.trim()),
+            }
         }
-        thread::sleep(Duration::from_millis(1));
-        eprintln!("{board}");
-        print_h(&board);
-        if args.black {
-            if let (Some((mov, new_board)), _h) = minimax(&board, false) {
-                println!("{}", mov.notation());
-                board = new_board;
-            } else {
+        self.stop_search();
+        self.end_game();
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Amazons => {
+                respond(Response::Id {
+                    field: "name".to_string(),
+                    value: env!("CARGO_PKG_NAME").to_string(),
+                });
+                respond(Response::Option {
+                    name: "Hash".to_string(),
+                    details: format!("type spin default {} min 0 max 65536", self.args.hash),
+                });
+                let names: Vec<String> = EVALUATORS
+                    .iter()
+                    .map(|evaluator| format!("var {}", evaluator.name()))
+                    .collect();
+                respond(Response::Option {
+                    name: "Eval".to_string(),
+                    details: format!(
+                        "type combo default {} {}",
+                        self.args.eval.name(),
+                        names.join(" ")
+                    ),
+                });
+                respond(Response::Option {
+                    name: "Threads".to_string(),
+                    details: format!(
+                        "type spin default {} min 1 max {MAX_THREADS}",
+                        self.args.threads
+                    ),
+                });
+                respond(Response::AmazonsOk);
+            }
+            // Commands are handled in order, so we're always ready by the time we read this
+            Command::IsReady => respond(Response::ReadyOk),
+            Command::SetOption { name, value } => {
+                self.stop_search();
+                self.set_option(&name, &value);
+            }
+            Command::NewGame => {
+                self.stop_search();
+                self.end_game();
+                self.searcher
+                    .as_mut()
+                    .expect("No search is running")
+                    .clear();
+            }
+            Command::Position { start, moves } => {
+                self.stop_search();
+                self.our_move = None;
+                self.board = start.clone();
+                self.record.start = start;
+                self.record.moves.clear();
+                for mov in moves {
+                    if let Err(err) = self.board.try_apply_move(&mov) {
+                        warn!("Illegal move {mov} in position: {err}, ignoring the rest");
+                        break;
+                    }
+                    self.record.moves.push(mov);
+                }
+            }
+            Command::Go(go) => {
+                self.stop_search();
+                self.our_move = None;
+                self.playing_white = Some(self.board.white_to_move);
+                let mut limits = if go.has_limits() {
+                    go.search_limits(self.board.white_to_move, self.args.default_move_time())
+                } else {
+                    self.args.search_limits()
+                };
+                let stop = Arc::new(AtomicBool::new(false));
+                limits.stop = Some(stop.clone());
+                limits.threads = self.threads;
+                let board = self.board.clone();
+                let mut searcher = self.searcher.take().expect("No search is running");
+                let evaluator = self.evaluator;
+                let handle = thread::spawn(move || {
+                    let (mov, _) = searcher.search(&board, &limits, evaluator);
+                    respond(Response::BestMove(mov));
+                    (searcher, mov)
+                });
+                self.search = Some(Search { stop, handle });
+            }
+            Command::Stop => self.stop_search(),
+            Command::Quit => unreachable!("Quit is handled by run"),
+        }
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) {
+        match name {
+            "Hash" => match value.parse() {
+                Ok(megabytes) => {
+                    if let Some(Searcher::Minimax(tt)) = &mut self.searcher {
+                        *tt = TranspositionTable::with_size_mb(megabytes);
+                    }
+                }
+                Err(err) => warn!("Invalid Hash {value:?}: {err}"),
+            },
+            "Eval" => match parse_evaluator(value) {
+                Ok(evaluator) => self.evaluator = evaluator,
+                Err(err) => warn!("Invalid Eval {value:?}: {err}"),
+            },
+            "Threads" => match parse_threads(value) {
+                Ok(threads) => self.threads = threads,
+                Err(err) => warn!("Invalid Threads {value:?}: {err}"),
+            },
+            _ => warn!("Unknown option {name:?}"),
+        }
+    }
+
+    /// Saves the game so far, including the move we chose last if the GUI never sent it back, and
+    /// starts a new record.
+    fn end_game(&mut self) {
+        if let Some(mov) = self.our_move.take() {
+            self.record.moves.push(mov);
+        }
+        if let Some(playing_white) = self.playing_white.take() {
+            let (white, black) = players(playing_white);
+            self.record.set_header("White", white);
+            self.record.set_header("Black", black);
+        }
+        if !self.record.moves.is_empty() {
+            self.args.save_record(&mut self.record);
+        }
+        self.record = self.args.new_record();
+    }
+
+    /// Stops the current search, if there is one, and waits for it to print its move.
+    fn stop_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop.store(true, Ordering::Relaxed);
+            let (searcher, mov) = search.handle.join().expect("Search thread panicked");
+            self.searcher = Some(searcher);
+            self.our_move = mov;
+        }
+    }
+}
+
+/// The names of the white and black players for a game record.
+fn players(playing_white: bool) -> (&'static str, &'static str) {
+    if playing_white {
+        (env!("CARGO_PKG_NAME"), "Opponent")
+    } else {
+        ("Opponent", env!("CARGO_PKG_NAME"))
+    }
+}
+
+fn respond(response: Response) {
+    println!("{response}");
+}
+
+/// Plays a whole game using the original protocol, printing our moves and reading the opponent's
+/// as bare lines.
+fn run_legacy(args: &Args) {
+    let mut board = args.fen.clone().unwrap_or_default();
+    let mut searcher = Searcher::new(args);
+    let mut limits = args.search_limits();
+    let mut record = args.new_record();
+    let (white, black) = players(!args.black);
+    record.set_header("White", white);
+    record.set_header("Black", black);
+    loop {
+        match board.outcome() {
+            Outcome::Ongoing => {}
+            Outcome::WhiteWins => {
                 println!("White wins");
                 break;
             }
+            Outcome::BlackWins => {
+                println!("Black wins");
+                break;
+            }
+        }
+        if board.white_to_move != args.black {
+            let start_time = Instant::now();
+            let (Some(mov), _h) = searcher.search(&board, &limits, args.eval) else {
+                unreachable!("The game is ongoing, so there must be a legal move");
+            };
+            if let Some(remaining) = &mut limits.remaining {
+                *remaining = remaining.saturating_sub(start_time.elapsed()) + limits.increment;
+            }
+            println!("{}", mov.notation());
+            board.apply_move(&mov);
+            record.moves.push(mov);
         } else {
-            input.clear();
-            stdin.read_line(&mut input).expect("Error reading input");
-            eprintln!("Read line [{input}]");
-            board.apply_move(&Move::parse_notation(&input).expect("Failed to parse notation"));
+            record.moves.push(read_move(&mut board));
         }
         thread::sleep(Duration::from_millis(1));
-        eprintln!("{board}");
+        debug!("position {}\n{board}", board.to_fen());
         print_h(&board);
     }
+    args.save_record(&mut record);
+}
+
+/// Reads the opponent's move from stdin and applies it, ignoring any lines that are not legal
+/// moves.
+fn read_move(board: &mut Board) -> Move {
+    let stdin = io::stdin();
+    let mut input = String::new();
+    loop {
+        input.clear();
+        if stdin.read_line(&mut input).expect("Error reading input") == 0 {
+            warn!("Opponent disconnected");
+            process::exit(1);
+        }
+        trace!("Read line [{input}]");
+        let mov = match Move::parse_notation(&input) {
+            Ok(mov) => mov,
+            Err(err) => {
+                warn!("Failed to parse notation: {err}, ignoring");
+                continue;
+            }
+        };
+        match board.try_apply_move(&mov) {
+            Ok(()) => return mov,
+            Err(err) => warn!("Illegal move {mov}: {err}, ignoring"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -58,7 +561,6 @@ mod test {
     #[test]
     fn test_notation() {
         let all_coords: Vec<Coord> = (0..100).collect();
-        let board: Board = Default::default();
         for a in all_coords.iter() {
             for b in all_coords.iter() {
                 for c in all_coords.iter() {
@@ -0,0 +1,500 @@
+//! Monte Carlo tree search, an alternative to [`crate::minimax`] that copes better with the huge
+//! number of moves available early in the game.
+//!
+//! Each iteration walks down the tree picking children with UCT, estimates how good the position
+//! it reaches is with a playout, and adds the result to every node on the way back up. The move
+//! visited most often at the root is played. The tree is kept between moves, so if the game
+//! continues down a line that has already been explored, that work isn't thrown away.
+
+use crate::{better_reachable_heuristic, Board, Move, SearchLimits, MMT, SEARCH_INFO_TARGET};
+use log::{debug, info};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    sync::atomic::Ordering,
+    time::Instant,
+};
+
+/// How many times a leaf must be visited before its children are added to the tree. Expanding
+/// every leaf straight away would need memory for thousands of children per iteration.
+const EXPANSION_VISITS: u32 = 8;
+
+/// The tree stops growing once it has this many nodes, about 100MB, and the search carries on
+/// refining the statistics of the nodes it already has.
+const MAX_TREE_NODES: usize = 2_000_000;
+
+/// How much [`better_reachable_heuristic`] is scaled down by before turning it into a probability
+/// of winning. A lead of this much is worth about a 73% chance of winning.
+const HEURISTIC_SCALE: f64 = 10.0;
+
+/// How the value of a position is estimated once the search reaches the edge of the tree.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Playout {
+    /// Play random moves until the game is over, and score 1 for a win and 0 for a loss.
+    Random,
+    /// Play [`MctsConfig::cutoff_depth`] random moves, then turn [`better_reachable_heuristic`]
+    /// into a probability of winning.
+    Heuristic,
+}
+
+impl Display for Playout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Playout::Random => "random",
+            Playout::Heuristic => "heuristic",
+        })
+    }
+}
+
+impl FromStr for Playout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Playout::Random),
+            "heuristic" => Ok(Playout::Heuristic),
+            _ => Err("expected one of random, heuristic".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MctsConfig {
+    /// How much UCT favours rarely visited moves over ones that have scored well so far
+    pub exploration: f64,
+    pub playout: Playout,
+    /// How many random moves a [`Playout::Heuristic`] plays before evaluating the position
+    pub cutoff_depth: usize,
+    /// Seeds the random numbers used to order new children and play out games, so a search can be
+    /// repeated exactly. The generator is reseeded at the start of every game. `None` picks a new
+    /// seed each time.
+    pub seed: Option<u64>,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self {
+            exploration: std::f64::consts::SQRT_2,
+            playout: Playout::Heuristic,
+            cutoff_depth: 4,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    /// The move that leads to this node from its parent. Meaningless for the root.
+    mov: Move,
+    visits: u32,
+    /// The total score of the playouts through this node, for the player who played `mov`
+    wins: f64,
+    /// The children are `first_child..first_child + child_count` in [`Mcts::nodes`]
+    first_child: u32,
+    child_count: u32,
+    /// Whether the children have been added. A node that is expanded but has no children is the
+    /// end of the game.
+    expanded: bool,
+}
+
+impl Node {
+    fn new(mov: Move) -> Self {
+        Self {
+            mov,
+            visits: 0,
+            wins: 0.0,
+            first_child: 0,
+            child_count: 0,
+            expanded: false,
+        }
+    }
+
+    fn children(&self) -> std::ops::Range<usize> {
+        let first = self.first_child as usize;
+        first..first + self.child_count as usize
+    }
+
+    /// The average score for the player who played `mov`.
+    fn value(&self) -> f64 {
+        if self.visits == 0 {
+            0.5
+        } else {
+            self.wins / f64::from(self.visits)
+        }
+    }
+}
+
+/// A Monte Carlo search tree, kept from one move to the next.
+pub struct Mcts {
+    pub config: MctsConfig,
+    /// Every node in the tree, with the root first and the children of each node next to each
+    /// other
+    nodes: Vec<Node>,
+    /// The position at the root of the tree
+    root: Board,
+    /// The seed `rng` was last seeded with
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Mcts {
+    pub fn new(config: MctsConfig) -> Self {
+        let root = Board::default();
+        let seed = config.seed.unwrap_or_else(rand::random);
+        Self {
+            config,
+            nodes: vec![Node::new(Move(0, 0, 0))],
+            root,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Forgets everything that has been searched and reseeds the random numbers, e.g. before a
+    /// new game.
+    pub fn clear(&mut self) {
+        self.set_root(Board::default(), usize::MAX);
+        self.seed = self.config.seed.unwrap_or_else(rand::random);
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+
+    /// The seed the random numbers were last seeded with. Passing it as [`MctsConfig::seed`]
+    /// replays the game exactly, given the same moves from the opponent.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// How many playouts have been run from the current root, including those from earlier
+    /// searches that reached it.
+    pub fn root_visits(&self) -> u32 {
+        self.nodes[0].visits
+    }
+
+    /// Searches `board` until one of the `limits` is reached, other than `max_depth` which doesn't
+    /// apply, so a search limited only by depth gets the default move time instead. `max_nodes`
+    /// limits the number of playouts. The returned score is the estimated probability that white
+    /// wins.
+    pub fn search(&mut self, board: &Board, limits: &SearchLimits) -> (Option<Move>, MMT) {
+        let start_time = Instant::now();
+        let budget = match limits.time_budget(board) {
+            None if limits.max_depth.is_some() && limits.max_nodes.is_none() => {
+                SearchLimits::default().move_time
+            }
+            budget => budget,
+        };
+        let deadline = budget.map(|budget| start_time + budget);
+        self.advance_to(board);
+        let reused = self.nodes[0].visits;
+        let mut scratch = board.clone();
+        let mut path = vec![];
+        let mut moves = vec![];
+        let mut playouts = 0;
+        loop {
+            let stopped = limits.max_nodes.is_some_and(|max| playouts >= max)
+                || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                || limits
+                    .stop
+                    .as_ref()
+                    .is_some_and(|stop| stop.load(Ordering::Relaxed));
+            // Always run at least one playout so the root has been expanded
+            if stopped && playouts > 0 {
+                break;
+            }
+            scratch.clone_from(board);
+            self.iterate(&mut scratch, &mut path, &mut moves);
+            playouts += 1;
+            if self.nodes[0].child_count == 0 {
+                // The game is over, there is nothing to search
+                break;
+            }
+        }
+
+        let pv = self.principal_variation();
+        let best = pv.first().map(|&node| self.nodes[node]);
+        // The chance that the player to move wins, for the info line
+        let winrate = best.map_or(0.0, |node| node.value());
+        let score = if board.white_to_move {
+            winrate
+        } else {
+            1.0 - winrate
+        };
+        let pv_moves: Vec<String> = pv
+            .iter()
+            .map(|&node| self.nodes[node].mov.notation())
+            .collect();
+        info!(
+            target: SEARCH_INFO_TARGET,
+            "depth {} winrate {winrate:.3} nodes {playouts} time {} pv {}",
+            pv.len(),
+            start_time.elapsed().as_millis(),
+            pv_moves.join(" ")
+        );
+        debug!(
+            "Ran {playouts} playouts in {:?}, reusing {reused} from earlier searches, with {} nodes in the tree and seed {}",
+            start_time.elapsed(),
+            self.nodes.len(),
+            self.seed
+        );
+        (best.map(|node| node.mov), score)
+    }
+
+    /// Moves the root of the tree to `board`, keeping the subtree below it if `board` is one or two
+    /// moves on from the current root.
+    fn advance_to(&mut self, board: &Board) {
+        if *board == self.root {
+            return;
+        }
+        let mut scratch = self.root.clone();
+        for child in self.nodes[0].children() {
+            let undo = scratch.apply_move(&self.nodes[child].mov);
+            if scratch == *board {
+                self.set_root(scratch, child);
+                return;
+            }
+            for grandchild in self.nodes[child].children() {
+                let undo = scratch.apply_move(&self.nodes[grandchild].mov);
+                if scratch == *board {
+                    self.set_root(scratch, grandchild);
+                    return;
+                }
+                scratch.unmake_move(undo);
+            }
+            scratch.unmake_move(undo);
+        }
+        self.set_root(board.clone(), usize::MAX);
+    }
+
+    /// Makes `node` the root of the tree, dropping everything that isn't below it. `usize::MAX`
+    /// starts a new tree.
+    fn set_root(&mut self, board: Board, node: usize) {
+        self.root = board;
+        let Some(&root) = self.nodes.get(node) else {
+            self.nodes = vec![Node::new(Move(0, 0, 0))];
+            return;
+        };
+        let mut nodes = vec![root];
+        let mut queue = VecDeque::from([(node, 0)]);
+        while let Some((old, new)) = queue.pop_front() {
+            nodes[new].first_child = nodes.len() as u32;
+            for child in self.nodes[old].children() {
+                queue.push_back((child, nodes.len()));
+                nodes.push(self.nodes[child]);
+            }
+        }
+        self.nodes = nodes;
+    }
+
+    /// Runs one playout from `board`, which must be the root position, and updates the tree.
+    fn iterate(&mut self, board: &mut Board, path: &mut Vec<usize>, moves: &mut Vec<Move>) {
+        path.clear();
+        path.push(0);
+        let mut node = 0;
+        loop {
+            if !self.nodes[node].expanded {
+                if (node != 0 && self.nodes[node].visits < EXPANSION_VISITS)
+                    || self.nodes.len() >= MAX_TREE_NODES
+                {
+                    break;
+                }
+                self.expand(node, board, moves);
+            }
+            if self.nodes[node].child_count == 0 {
+                break;
+            }
+            node = self.select(node);
+            board.apply_move(&self.nodes[node].mov);
+            path.push(node);
+        }
+        // The score for the player who moved into the final position
+        let mut score = 1.0 - self.playout(board, moves);
+        for &node in path.iter().rev() {
+            self.nodes[node].visits += 1;
+            self.nodes[node].wins += score;
+            score = 1.0 - score;
+        }
+    }
+
+    fn expand(&mut self, node: usize, board: &Board, moves: &mut Vec<Move>) {
+        moves.clear();
+        board.generate_moves(board.side_to_move_range(), moves);
+        // Unvisited children are tried in order, so shuffle them to avoid favouring one amazon
+        moves.shuffle(&mut self.rng);
+        self.nodes[node].first_child = self.nodes.len() as u32;
+        self.nodes[node].child_count = moves.len() as u32;
+        self.nodes[node].expanded = true;
+        self.nodes.extend(moves.iter().map(|&mov| Node::new(mov)));
+    }
+
+    /// The child of `node` with the highest upper confidence bound.
+    fn select(&self, node: usize) -> usize {
+        let log_visits = f64::from(self.nodes[node].visits.max(1)).ln();
+        let mut best = (0, f64::NEG_INFINITY);
+        for child in self.nodes[node].children() {
+            let Node { visits, wins, .. } = self.nodes[child];
+            if visits == 0 {
+                return child;
+            }
+            let visits = f64::from(visits);
+            let bound = wins / visits + self.config.exploration * (log_visits / visits).sqrt();
+            if bound > best.1 {
+                best = (child, bound);
+            }
+        }
+        best.0
+    }
+
+    /// Estimates the chance that the player to move in `board` wins.
+    fn playout(&mut self, board: &mut Board, moves: &mut Vec<Move>) -> f64 {
+        let player = board.white_to_move;
+        let max_moves = match self.config.playout {
+            Playout::Random => usize::MAX,
+            Playout::Heuristic => self.config.cutoff_depth,
+        };
+        for _ in 0..max_moves {
+            moves.clear();
+            board.generate_moves(board.side_to_move_range(), moves);
+            if moves.is_empty() {
+                // The player to move has lost
+                return if board.white_to_move == player {
+                    0.0
+                } else {
+                    1.0
+                };
+            }
+            let mov = moves[self.rng.gen_range(0..moves.len())];
+            board.apply_move(&mov);
+        }
+        let white_lead = better_reachable_heuristic(board) / HEURISTIC_SCALE;
+        let white_wins = 1.0 / (1.0 + (-white_lead).exp());
+        if player {
+            white_wins
+        } else {
+            1.0 - white_wins
+        }
+    }
+
+    /// The most visited child of the root, then the most visited child of that, and so on.
+    fn principal_variation(&self) -> Vec<usize> {
+        let mut pv = vec![];
+        let mut node = 0;
+        while let Some(best) = self.nodes[node]
+            .children()
+            .filter(|&child| self.nodes[child].visits > 0)
+            .max_by_key(|&child| self.nodes[child].visits)
+        {
+            pv.push(best);
+            node = best;
+        }
+        pv
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{minimax, BetterReachableHeuristic, TranspositionTable, WIN};
+
+    fn limits(playouts: usize) -> SearchLimits {
+        SearchLimits {
+            move_time: None,
+            max_nodes: Some(playouts),
+            ..SearchLimits::default()
+        }
+    }
+
+    #[test]
+    fn test_finds_win() {
+        // Black's only free amazon is on j1, and can only escape through i1. White's amazon on f4
+        // can trap it by moving to or firing at i1, or by firing at h2 to leave it one move.
+        let board: Board = "BxBxBxxxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/\
+                            WxWxWW4/xxxxxx4/xxxxxx2xx/xxxxxxxx1B w 60"
+            .parse()
+            .unwrap();
+        let depth_limit = SearchLimits {
+            move_time: None,
+            max_depth: Some(3),
+            ..SearchLimits::default()
+        };
+        for playout in [Playout::Random, Playout::Heuristic] {
+            let mut mcts = Mcts::new(MctsConfig {
+                playout,
+                seed: Some(1),
+                ..MctsConfig::default()
+            });
+            let (mov, score) = mcts.search(&board, &limits(2_000));
+            let mut after = board.clone();
+            after.try_apply_move(&mov.unwrap()).unwrap();
+            let mut tt = TranspositionTable::new(0);
+            let (_, proof) = minimax(&after, &mut tt, &depth_limit, &BetterReachableHeuristic);
+            assert!(proof >= WIN, "{playout} played {mov:?}");
+            assert!(score > 0.9, "{score}");
+        }
+    }
+
+    #[test]
+    fn test_seed() {
+        let board: Board = "BxBxBx4/xxxxxx3B/xxxxxx4/xxxxxxxxxx/xxxxxx4/xxxxxx4/WxWxWW4/\
+                            xxxxxx4/xxxxxx4/xxxxxx4 w 40"
+            .parse()
+            .unwrap();
+        let config = MctsConfig {
+            seed: Some(7),
+            playout: Playout::Random,
+            ..MctsConfig::default()
+        };
+        let mut first = Mcts::new(config);
+        let mut second = Mcts::new(config);
+        let expected = first.search(&board, &limits(500));
+        assert_eq!(second.search(&board, &limits(500)), expected);
+        let visits: Vec<u32> = first.nodes.iter().map(|node| node.visits).collect();
+        assert!(second.nodes.iter().map(|node| node.visits).eq(visits));
+
+        // A new game starts from the same seed
+        second.clear();
+        assert_eq!(second.search(&board, &limits(500)), expected);
+        assert_eq!(second.seed(), 7);
+    }
+
+    #[test]
+    fn test_game_over() {
+        let board: Board = "BxBxBxBx2/xxxxxxxx2/10/10/10/10/10/10/xxxxxxx3/WxWxWxW3 b 1"
+            .parse()
+            .unwrap();
+        let mut mcts = Mcts::new(MctsConfig::default());
+        let (mov, score) = mcts.search(&board, &limits(100));
+        assert_eq!(mov, None);
+        assert_eq!(score, 1.0);
+    }
+
+    #[test]
+    fn test_tree_reuse() {
+        // Each side has one amazon free in a small region, so the tree gets deep enough to have
+        // the replies to our moves
+        let mut board: Board = "BxBxBx4/xxxxxx3B/xxxxxx4/xxxxxxxxxx/xxxxxx4/xxxxxx4/WxWxWW4/\
+                                xxxxxx4/xxxxxx4/xxxxxx4 w 40"
+            .parse()
+            .unwrap();
+        let mut mcts = Mcts::new(MctsConfig::default());
+        let (mov, _) = mcts.search(&board, &limits(5_000));
+        let mov = mov.unwrap();
+        board.apply_move(&mov);
+        // Follow the line the search expects, which is sure to have been explored
+        let pv = mcts.principal_variation();
+        let reply = mcts.nodes[pv[1]].mov;
+        let expected_visits = mcts.nodes[pv[1]].visits;
+        board.apply_move(&reply);
+        mcts.advance_to(&board);
+        assert_eq!(mcts.root_visits(), expected_visits);
+        assert!(expected_visits > 0);
+        let (mov, _) = mcts.search(&board, &limits(1_000));
+        assert!(board.is_legal(&mov.unwrap()));
+        assert_eq!(mcts.root_visits(), expected_visits + 1_000);
+
+        // An unrelated position starts again
+        mcts.advance_to(&Board::default());
+        assert_eq!(mcts.root_visits(), 0);
+        assert_eq!(mcts.nodes.len(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+//! Owns an AI's child process, so it is always reaped and never outlives the GUI or match runner
+//! that started it.
+
+use crate::reader::{spawn_line_reader, ReaderEvent};
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+/// Why no more output can be read from an [`EngineProcess`].
+#[derive(Debug)]
+pub enum ProcessError {
+    /// The process closed its output and exited.
+    Exited(ExitStatus),
+    /// Reading the process's output failed.
+    Io(io::Error),
+}
+
+impl Display for ProcessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessError::Exited(status) => write!(f, "the process exited with {status}"),
+            ProcessError::Io(err) => write!(f, "failed to read from the process: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+/// A running child process that we talk to one line at a time. Its stdout and stderr are read on
+/// background threads, so none of these methods block waiting for output. The process is killed
+/// when this is dropped.
+pub struct EngineProcess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: Receiver<ReaderEvent>,
+    stderr: Receiver<ReaderEvent>,
+}
+
+impl EngineProcess {
+    pub fn spawn<S: AsRef<str>>(program: &str, args: &[S]) -> io::Result<Self> {
+        let mut child = Command::new(program)
+            .args(args.iter().map(AsRef::as_ref))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // These are always present, since all three were piped above
+        let stdout = spawn_line_reader(child.stdout.take().expect("stdout is piped"));
+        let stderr = spawn_line_reader(child.stderr.take().expect("stderr is piped"));
+        Ok(Self {
+            stdin: child.stdin.take(),
+            child,
+            stdout,
+            stderr,
+        })
+    }
+
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        let Some(stdin) = &mut self.stdin else {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        };
+        writeln!(stdin, "{line}")?;
+        stdin.flush()
+    }
+
+    /// The next line the process has written to stdout, or `None` if it hasn't written a full line
+    /// yet. Once the process closes its output, this waits for it to exit and reports how it
+    /// did.
+    pub fn try_read_line(&mut self) -> Result<Option<String>, ProcessError> {
+        let event = match self.stdout.try_recv() {
+            Ok(event) => event,
+            Err(TryRecvError::Empty) => return Ok(None),
+            Err(TryRecvError::Disconnected) => ReaderEvent::Closed,
+        };
+        self.line_from_event(event).map(Some)
+    }
+
+    /// Like [`EngineProcess::try_read_line`], but waits up to `timeout` for a line to arrive.
+    pub fn read_line_timeout(&mut self, timeout: Duration) -> Result<Option<String>, ProcessError> {
+        let event = match self.stdout.recv_timeout(timeout) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return Ok(None),
+            Err(RecvTimeoutError::Disconnected) => ReaderEvent::Closed,
+        };
+        self.line_from_event(event).map(Some)
+    }
+
+    fn line_from_event(&mut self, event: ReaderEvent) -> Result<String, ProcessError> {
+        match event {
+            ReaderEvent::Line(line) => Ok(line),
+            ReaderEvent::Closed => self.child.wait().map_or_else(
+                |err| Err(ProcessError::Io(err)),
+                |status| Err(ProcessError::Exited(status)),
+            ),
+            ReaderEvent::Failed(err) => Err(ProcessError::Io(err)),
+        }
+    }
+
+    /// Every line the process has written to stderr since the last call.
+    pub fn stderr_lines(&mut self) -> Vec<String> {
+        self.stderr
+            .try_iter()
+            .filter_map(|event| match event {
+                ReaderEvent::Line(line) => Some(line),
+                ReaderEvent::Closed | ReaderEvent::Failed(_) => None,
+            })
+            .collect()
+    }
+
+    /// Kills the process, if it's still running, and waits for it to exit.
+    pub fn kill(&mut self) {
+        // Closing stdin first lets well behaved engines notice and exit on their own
+        self.stdin = None;
+        // This only fails if the process has already exited
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for EngineProcess {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Instant;
+
+    /// Polls `f` until it returns something, or panics after a few seconds.
+    fn wait_for<T>(mut f: impl FnMut() -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(value) = f() {
+                return value;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_echo() {
+        let mut process = EngineProcess::spawn("sh", &["-c", "read line; echo \"got $line\""])
+            .expect("sh should start");
+        process.send_line("a1-a2/a3").unwrap();
+        let line = wait_for(|| process.try_read_line().unwrap());
+        assert_eq!(line, "got a1-a2/a3");
+        let result = wait_for(|| process.try_read_line().err());
+        assert!(matches!(result, ProcessError::Exited(status) if status.success()));
+    }
+
+    #[test]
+    fn test_crash_and_stderr() {
+        let mut process =
+            EngineProcess::spawn("sh", &["-c", "echo oops >&2; exit 3"]).expect("sh should start");
+        let result = wait_for(|| process.try_read_line().err());
+        assert!(matches!(result, ProcessError::Exited(status) if status.code() == Some(3)));
+        let stderr = wait_for(|| Some(process.stderr_lines()).filter(|lines| !lines.is_empty()));
+        assert_eq!(stderr, vec!["oops"]);
+    }
+
+    #[test]
+    fn test_read_line_timeout() {
+        let mut process =
+            EngineProcess::spawn("sh", &["-c", "sleep 0.2; echo late"]).expect("sh should start");
+        let start = Instant::now();
+        assert!(process
+            .read_line_timeout(Duration::from_millis(10))
+            .unwrap()
+            .is_none());
+        assert!(start.elapsed() < Duration::from_millis(200));
+        let line = process.read_line_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(line.as_deref(), Some("late"));
+    }
+
+    #[test]
+    fn test_kill() {
+        let mut process = EngineProcess::spawn("sleep", &["10"]).expect("sleep should start");
+        let start = Instant::now();
+        process.kill();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(process.child.try_wait().unwrap().is_some());
+        assert!(process.send_line("anyone there?").is_err());
+    }
+}
@@ -0,0 +1,501 @@
+//! The text protocol spoken between a GUI and the engine, modelled on UCI. Every message is a
+//! single line. A session looks like:
+//!
+//! ```text
+//! GUI:    amazons
+//! Engine: id name amazons_core
+//! Engine: option name Hash type spin default 64 min 0 max 65536
+//! Engine: amazonsok
+//! GUI:    isready
+//! Engine: readyok
+//! GUI:    newgame
+//! GUI:    position startpos moves d1-d7/g7
+//! GUI:    go wtime 60000 btime 60000
+//! Engine: bestmove g10-g8/e6
+//! GUI:    quit
+//! ```
+//!
+//! The engine doesn't play the moves it suggests, the GUI sends the whole game with `position`
+//! before every `go`. Games that didn't start from the usual position can be sent with
+//! `position fen <fen> moves ...`, using the format from [`Board::to_fen`].
+
+use crate::{Board, FenError, Move, ParseError, SearchLimits};
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Why a protocol message could not be parsed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProtocolError {
+    /// The line didn't start with a known message.
+    Unknown(String),
+    /// A message was missing an argument it needs.
+    Missing(&'static str),
+    /// An argument to a message had the wrong format.
+    Invalid(&'static str, String),
+    /// One of the moves couldn't be parsed.
+    Move(ParseError),
+    /// The position couldn't be parsed.
+    Fen(FenError),
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Unknown(line) => write!(f, "unknown message {line:?}"),
+            ProtocolError::Missing(what) => write!(f, "missing {what}"),
+            ProtocolError::Invalid(what, seen) => write!(f, "invalid {what} {seen:?}"),
+            ProtocolError::Move(error) => write!(f, "{error}"),
+            ProtocolError::Fen(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<ParseError> for ProtocolError {
+    fn from(error: ParseError) -> Self {
+        ProtocolError::Move(error)
+    }
+}
+
+impl From<FenError> for ProtocolError {
+    fn from(error: FenError) -> Self {
+        ProtocolError::Fen(error)
+    }
+}
+
+/// A message from the GUI to the engine.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Start of the session. The engine replies with its `id` and `option`s, then `amazonsok`.
+    Amazons,
+    /// The engine replies `readyok` once it has finished handling the previous commands.
+    IsReady,
+    SetOption {
+        name: String,
+        value: String,
+    },
+    /// The next search will be from a different game, so anything remembered can be forgotten.
+    NewGame,
+    /// `start` followed by `moves`.
+    Position {
+        start: Board,
+        moves: Vec<Move>,
+    },
+    /// Start searching the current position. The engine replies with `bestmove` when done.
+    Go(Go),
+    /// Finish searching as soon as possible.
+    Stop,
+    Quit,
+}
+
+/// The limits for a search started by [`Command::Go`]. Times are in milliseconds on the wire.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Go {
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Duration,
+    pub binc: Duration,
+    pub movetime: Option<Duration>,
+    pub depth: Option<usize>,
+    pub nodes: Option<usize>,
+    /// Search until told to `stop`
+    pub infinite: bool,
+}
+
+impl Go {
+    /// Whether any limits were given. If not, the engine picks its own.
+    pub fn has_limits(&self) -> bool {
+        *self != Go::default()
+    }
+
+    /// The limits to search with when `white_to_move` is the side to move. If nothing would stop
+    /// the search, e.g. only the opponent's clock or an increment was given, it gets
+    /// `default_move_time` instead of running until `stop`.
+    pub fn search_limits(
+        &self,
+        white_to_move: bool,
+        default_move_time: Option<Duration>,
+    ) -> SearchLimits {
+        let (remaining, increment) = if white_to_move {
+            (self.wtime, self.winc)
+        } else {
+            (self.btime, self.binc)
+        };
+        let unlimited = !self.infinite
+            && self.movetime.is_none()
+            && remaining.is_none()
+            && self.depth.is_none()
+            && self.nodes.is_none();
+        SearchLimits {
+            move_time: if unlimited {
+                default_move_time
+            } else {
+                self.movetime
+            },
+            remaining: if self.infinite { None } else { remaining },
+            increment,
+            max_depth: self.depth,
+            max_nodes: self.nodes,
+            ..SearchLimits::infinite()
+        }
+    }
+}
+
+impl Display for Go {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("go")?;
+        let millis = [
+            ("wtime", self.wtime),
+            ("btime", self.btime),
+            ("winc", Some(self.winc).filter(|winc| !winc.is_zero())),
+            ("binc", Some(self.binc).filter(|binc| !binc.is_zero())),
+            ("movetime", self.movetime),
+        ];
+        for (name, value) in millis {
+            if let Some(value) = value {
+                write!(f, " {name} {}", value.as_millis())?;
+            }
+        }
+        for (name, value) in [("depth", self.depth), ("nodes", self.nodes)] {
+            if let Some(value) = value {
+                write!(f, " {name} {value}")?;
+            }
+        }
+        if self.infinite {
+            f.write_str(" infinite")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Go {
+    type Err = ProtocolError;
+
+    /// Parses the arguments following `go`.
+    fn from_str(args: &str) -> Result<Self, Self::Err> {
+        let mut go = Go::default();
+        let mut tokens = args.split_whitespace();
+        while let Some(token) = tokens.next() {
+            if token == "infinite" {
+                go.infinite = true;
+                continue;
+            }
+            let name = match token {
+                "wtime" => "wtime",
+                "btime" => "btime",
+                "winc" => "winc",
+                "binc" => "binc",
+                "movetime" => "movetime",
+                "depth" => "depth",
+                "nodes" => "nodes",
+                _ => return Err(ProtocolError::Invalid("go argument", token.to_string())),
+            };
+            let value = tokens.next().ok_or(ProtocolError::Missing(name))?;
+            let value: u64 = value
+                .parse()
+                .map_err(|_| ProtocolError::Invalid(name, value.to_string()))?;
+            let millis = Duration::from_millis(value);
+            match name {
+                "wtime" => go.wtime = Some(millis),
+                "btime" => go.btime = Some(millis),
+                "winc" => go.winc = millis,
+                "binc" => go.binc = millis,
+                "movetime" => go.movetime = Some(millis),
+                "depth" => go.depth = Some(value as usize),
+                _ => go.nodes = Some(value as usize),
+            }
+        }
+        Ok(go)
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Amazons => f.write_str("amazons"),
+            Command::IsReady => f.write_str("isready"),
+            Command::SetOption { name, value } => write!(f, "setoption name {name} value {value}"),
+            Command::NewGame => f.write_str("newgame"),
+            Command::Position { start, moves } => {
+                if *start == Board::default() {
+                    f.write_str("position startpos")?;
+                } else {
+                    write!(f, "position fen {}", start.to_fen())?;
+                }
+                if !moves.is_empty() {
+                    f.write_str(" moves")?;
+                    for mov in moves {
+                        write!(f, " {mov}")?;
+                    }
+                }
+                Ok(())
+            }
+            Command::Go(go) => write!(f, "{go}"),
+            Command::Stop => f.write_str("stop"),
+            Command::Quit => f.write_str("quit"),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        match name {
+            "amazons" => Ok(Command::Amazons),
+            "isready" => Ok(Command::IsReady),
+            "setoption" => {
+                let args = args
+                    .strip_prefix("name ")
+                    .ok_or(ProtocolError::Missing("option name"))?;
+                let (name, value) = args
+                    .split_once(" value ")
+                    .ok_or(ProtocolError::Missing("option value"))?;
+                Ok(Command::SetOption {
+                    name: name.trim().to_string(),
+                    value: value.trim().to_string(),
+                })
+            }
+            "newgame" => Ok(Command::NewGame),
+            "position" => {
+                let (start, moves) = args.split_once("moves").unwrap_or((args, ""));
+                let start = match start.trim() {
+                    "" => return Err(ProtocolError::Missing("position")),
+                    "startpos" => Board::default(),
+                    start => match start.strip_prefix("fen ") {
+                        Some(fen) => fen.parse()?,
+                        None => return Err(ProtocolError::Invalid("position", start.into())),
+                    },
+                };
+                let moves = moves
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?;
+                Ok(Command::Position { start, moves })
+            }
+            "go" => Ok(Command::Go(args.parse()?)),
+            "stop" => Ok(Command::Stop),
+            "quit" => Ok(Command::Quit),
+            _ => Err(ProtocolError::Unknown(line.to_string())),
+        }
+    }
+}
+
+/// A message from the engine to the GUI.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Response {
+    /// Information about the engine, e.g. `id name amazons_core`.
+    Id {
+        field: String,
+        value: String,
+    },
+    /// An option that can be set with [`Command::SetOption`], e.g.
+    /// `option name Hash type spin default 64 min 0 max 65536`.
+    Option {
+        name: String,
+        details: String,
+    },
+    /// The engine has sent everything in response to [`Command::Amazons`].
+    AmazonsOk,
+    ReadyOk,
+    /// The result of a search. `None` if the player to move has no moves, and so has lost.
+    BestMove(Option<Move>),
+    /// Free-form information about what the engine is doing.
+    Info(String),
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Id { field, value } => write!(f, "id {field} {value}"),
+            Response::Option { name, details } => write!(f, "option name {name} {details}"),
+            Response::AmazonsOk => f.write_str("amazonsok"),
+            Response::ReadyOk => f.write_str("readyok"),
+            Response::BestMove(Some(mov)) => write!(f, "bestmove {mov}"),
+            Response::BestMove(None) => f.write_str("bestmove none"),
+            Response::Info(info) => write!(f, "info {info}"),
+        }
+    }
+}
+
+impl FromStr for Response {
+    type Err = ProtocolError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+        let args = args.trim();
+        match name {
+            "id" => {
+                let (field, value) = args.split_once(' ').ok_or(ProtocolError::Missing("id"))?;
+                Ok(Response::Id {
+                    field: field.to_string(),
+                    value: value.trim().to_string(),
+                })
+            }
+            "option" => {
+                let args = args
+                    .strip_prefix("name ")
+                    .ok_or(ProtocolError::Missing("option name"))?;
+                let (name, details) = args.split_once(' ').unwrap_or((args, ""));
+                Ok(Response::Option {
+                    name: name.to_string(),
+                    details: details.trim().to_string(),
+                })
+            }
+            "amazonsok" => Ok(Response::AmazonsOk),
+            "readyok" => Ok(Response::ReadyOk),
+            "bestmove" => match args {
+                "" => Err(ProtocolError::Missing("best move")),
+                "none" => Ok(Response::BestMove(None)),
+                mov => Ok(Response::BestMove(Some(mov.parse()?))),
+            },
+            "info" => Ok(Response::Info(args.to_string())),
+            _ => Err(ProtocolError::Unknown(line.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn mov(notation: &str) -> Move {
+        notation.parse().unwrap()
+    }
+
+    #[test]
+    fn test_command_round_trip() {
+        let commands = [
+            Command::Amazons,
+            Command::IsReady,
+            Command::SetOption {
+                name: "Hash".to_string(),
+                value: "128".to_string(),
+            },
+            Command::NewGame,
+            Command::Position {
+                start: Board::default(),
+                moves: vec![],
+            },
+            Command::Position {
+                start: "3B2B3/10/10/B8B/3x6/10/W8W/10/10/3W2W3 b 1"
+                    .parse()
+                    .unwrap(),
+                moves: vec![mov("d10-d7/d8")],
+            },
+            Command::Position {
+                start: Board::default(),
+                moves: vec![mov("d1-d7/g7"), mov("g10-g8/e6")],
+            },
+            Command::Go(Go::default()),
+            Command::Go(Go {
+                wtime: Some(Duration::from_secs(60)),
+                btime: Some(Duration::from_secs(50)),
+                winc: Duration::from_millis(100),
+                depth: Some(3),
+                ..Go::default()
+            }),
+            Command::Go(Go {
+                infinite: true,
+                ..Go::default()
+            }),
+            Command::Stop,
+            Command::Quit,
+        ];
+        for command in commands {
+            assert_eq!(command.to_string().parse(), Ok(command));
+        }
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let responses = [
+            Response::Id {
+                field: "name".to_string(),
+                value: "amazons core".to_string(),
+            },
+            Response::Option {
+                name: "Hash".to_string(),
+                details: "type spin default 64 min 0 max 65536".to_string(),
+            },
+            Response::AmazonsOk,
+            Response::ReadyOk,
+            Response::BestMove(Some(mov("a4-a5/a4"))),
+            Response::BestMove(None),
+            Response::Info("depth 3 score 1.5".to_string()),
+        ];
+        for response in responses {
+            assert_eq!(response.to_string().parse(), Ok(response));
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "position startpos moves a1-a2".parse::<Command>(),
+            Err(ProtocolError::Move(ParseError::Format("a1-a2".to_string())))
+        );
+        assert_eq!(
+            "go wtime".parse::<Command>(),
+            Err(ProtocolError::Missing("wtime"))
+        );
+        assert_eq!(
+            "go depth deep".parse::<Command>(),
+            Err(ProtocolError::Invalid("depth", "deep".to_string()))
+        );
+        assert!(matches!(
+            "position fen".parse::<Command>(),
+            Err(ProtocolError::Invalid("position", _))
+        ));
+        assert!(matches!(
+            "position fen 10/10 w 0 moves a1-a2/a3".parse::<Command>(),
+            Err(ProtocolError::Fen(FenError::RowCount(2)))
+        ));
+        assert!(matches!(
+            "position".parse::<Command>(),
+            Err(ProtocolError::Missing("position"))
+        ));
+        assert!(matches!(
+            "hello".parse::<Command>(),
+            Err(ProtocolError::Unknown(_))
+        ));
+        // Extra whitespace is fine
+        assert_eq!("  isready \n".parse(), Ok(Command::IsReady));
+        assert_eq!(
+            "bestmove a1-a2/a3".parse(),
+            Ok(Response::BestMove(Some(mov("a1-a2/a3"))))
+        );
+    }
+
+    #[test]
+    fn test_go_limits() {
+        let go: Go = "wtime 1000 btime 2000 winc 10 binc 20 depth 4"
+            .parse()
+            .unwrap();
+        assert!(go.has_limits());
+        let default = Some(Duration::from_secs(5));
+        let limits = go.search_limits(false, default);
+        assert_eq!(limits.remaining, Some(Duration::from_secs(2)));
+        assert_eq!(limits.increment, Duration::from_millis(20));
+        assert_eq!(limits.max_depth, Some(4));
+        assert_eq!(limits.move_time, None);
+        assert!(!Go::default().has_limits());
+
+        // Neither an increment nor the opponent's clock is enough to end the search
+        for go in ["winc 100", "btime 1000", "wtime 1000 binc 10"] {
+            let go: Go = go.parse().unwrap();
+            let limits = go.search_limits(go.wtime.is_none(), default);
+            assert_eq!(limits.move_time, default, "{go}");
+        }
+        let go: Go = "infinite".parse().unwrap();
+        assert_eq!(go.search_limits(true, default).move_time, None);
+    }
+}
@@ -0,0 +1,64 @@
+//! Reads a child process's output on a background thread, so nothing blocks waiting for an engine
+//! to answer.
+
+use std::io::{BufRead, BufReader, Read};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+/// Something that happened to the stream being read.
+#[derive(Debug)]
+pub enum ReaderEvent {
+    /// A line of output, without the trailing newline.
+    Line(String),
+    /// The stream was closed, normally because the process exited.
+    Closed,
+    /// Reading failed. Nothing more will be sent after this.
+    Failed(std::io::Error),
+}
+
+/// Starts a thread that sends each line read from `input` down the returned channel. The thread
+/// stops once the input is closed or the receiver is dropped.
+pub fn spawn_line_reader<R: Read + Send + 'static>(input: R) -> Receiver<ReaderEvent> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        for line in BufReader::new(input).lines() {
+            let event = match line {
+                Ok(line) => ReaderEvent::Line(line),
+                Err(err) => {
+                    let _ = sender.send(ReaderEvent::Failed(err));
+                    return;
+                }
+            };
+            if sender.send(event).is_err() {
+                // Nobody is listening any more
+                return;
+            }
+        }
+        let _ = sender.send(ReaderEvent::Closed);
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reads_lines_then_closes() {
+        let receiver = spawn_line_reader(&b"a1-a2/a3\r\n\nnot a move\n"[..]);
+        let events: Vec<ReaderEvent> = receiver.iter().collect();
+        assert!(matches!(&events[0], ReaderEvent::Line(line) if line == "a1-a2/a3"));
+        assert!(matches!(&events[1], ReaderEvent::Line(line) if line.is_empty()));
+        assert!(matches!(&events[2], ReaderEvent::Line(line) if line == "not a move"));
+        assert!(matches!(events[3], ReaderEvent::Closed));
+        assert_eq!(events.len(), 4);
+    }
+
+    #[test]
+    fn test_reports_errors() {
+        // Not valid UTF-8
+        let receiver = spawn_line_reader(&b"\xff\xfe\n"[..]);
+        let events: Vec<ReaderEvent> = receiver.iter().collect();
+        assert!(matches!(events[..], [ReaderEvent::Failed(_)]));
+    }
+}
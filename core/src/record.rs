@@ -0,0 +1,459 @@
+//! Game records in a text format modelled on chess's PGN. A record is a list of headers followed by
+//! the moves, numbered in pairs, and the result:
+//!
+//! ```text
+//! [White "amazons_core"]
+//! [Black "Opponent"]
+//! [Date "2024.08.20"]
+//! [Result "1-0"]
+//!
+//! 1. d1-d7/g7 g10-g8/e6 2. a4-b4/a4 1-0
+//! ```
+//!
+//! Games that don't start from the usual position have a `FEN` header, see [`Board::to_fen`]. Any
+//! number of records can be stored in one file, separated by blank lines.
+
+use crate::{Board, FenError, Move, MoveError, Outcome, ParseError};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Why a game record could not be read.
+#[derive(Debug)]
+pub enum RecordError {
+    /// A header line wasn't of the form `[Name "value"]`.
+    Header(String),
+    /// The `FEN` header couldn't be parsed.
+    Fen(FenError),
+    /// The move at this ply, counting from the start of the record, couldn't be parsed.
+    Notation(usize, ParseError),
+    /// The move at this ply, counting from the start of the record, isn't legal.
+    Illegal(usize, Move, MoveError),
+    /// The game is over, but the `Result` header disagrees about who won.
+    Result {
+        header: String,
+        actual: String,
+    },
+    Io(io::Error),
+}
+
+impl Display for RecordError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Header(line) => write!(f, "invalid header {line:?}"),
+            RecordError::Fen(error) => write!(f, "invalid FEN header: {error}"),
+            RecordError::Notation(ply, error) => write!(f, "move {}: {error}", ply + 1),
+            RecordError::Illegal(ply, mov, error) => {
+                write!(f, "move {} ({mov}) is illegal: {error}", ply + 1)
+            }
+            RecordError::Result { header, actual } => {
+                write!(f, "the result is {header} but the game ended {actual}")
+            }
+            RecordError::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<io::Error> for RecordError {
+    fn from(error: io::Error) -> Self {
+        RecordError::Io(error)
+    }
+}
+
+/// A game, from its starting position, along with information about who played it and how.
+#[derive(Clone, Debug, Default)]
+pub struct GameRecord {
+    /// Name and value pairs, in the order they are written. See [`GameRecord::header`].
+    pub headers: Vec<(String, String)>,
+    pub start: Board,
+    pub moves: Vec<Move>,
+    // The position after the first so many moves, so `push` doesn't have to replay the whole game.
+    // Only trusted while the number of moves matches.
+    end: Option<(usize, Board)>,
+}
+
+/// Records are equal if they have the same headers and moves from the same start.
+impl PartialEq for GameRecord {
+    fn eq(&self, other: &Self) -> bool {
+        self.headers == other.headers && self.start == other.start && self.moves == other.moves
+    }
+}
+
+/// The `Result` header for a game with this outcome.
+pub fn result_string(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Ongoing => "*",
+        Outcome::WhiteWins => "1-0",
+        Outcome::BlackWins => "0-1",
+    }
+}
+
+impl GameRecord {
+    /// An empty record of a game starting at `start`, with today's date.
+    pub fn new(start: Board) -> Self {
+        let mut record = GameRecord {
+            headers: vec![],
+            start,
+            moves: vec![],
+            end: None,
+        };
+        record.set_header("Date", &today());
+        record
+    }
+
+    /// The value of the header with this name, e.g. `White`, `Black`, `Date`, `TimeControl` or
+    /// `Result`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Replaces the header with this name, or adds it if there isn't one.
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self.headers.iter_mut().find(|(header, _)| header == name) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.headers.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Adds a move to the end of the game if it is legal.
+    pub fn push(&mut self, mov: Move) -> Result<(), RecordError> {
+        let ply = self.moves.len();
+        let mut board = match self.end.take() {
+            Some((end_ply, board)) if end_ply == ply => board,
+            _ => self.replay(ply)?,
+        };
+        let result = board
+            .try_apply_move(&mov)
+            .map_err(|error| RecordError::Illegal(ply, mov, error));
+        if result.is_ok() {
+            self.moves.push(mov);
+            self.end = Some((ply + 1, board));
+        } else {
+            self.end = Some((ply, board));
+        }
+        result
+    }
+
+    /// The position after the first `ply` moves, checking that each of them is legal. `ply` is
+    /// clamped to the length of the game.
+    pub fn replay(&self, ply: usize) -> Result<Board, RecordError> {
+        let mut board = self.start.clone();
+        for (i, mov) in self.moves.iter().take(ply).enumerate() {
+            board
+                .try_apply_move(mov)
+                .map_err(|error| RecordError::Illegal(i, *mov, error))?;
+        }
+        Ok(board)
+    }
+
+    /// The final position, after checking that every move is legal and that the `Result` header
+    /// agrees with how the game ended.
+    pub fn validate(&self) -> Result<Board, RecordError> {
+        let board = self.replay(self.moves.len())?;
+        let outcome = board.outcome();
+        if let (Some(header), Outcome::WhiteWins | Outcome::BlackWins) =
+            (self.header("Result"), outcome)
+        {
+            let actual = result_string(outcome);
+            if header != actual {
+                return Err(RecordError::Result {
+                    header: header.to_string(),
+                    actual: actual.to_string(),
+                });
+            }
+        }
+        Ok(board)
+    }
+
+    /// Sets the `Result` header from the final position.
+    pub fn set_result_from_board(&mut self) -> Result<(), RecordError> {
+        let outcome = self.replay(self.moves.len())?.outcome();
+        self.set_header("Result", result_string(outcome));
+        Ok(())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Adds this record to the end of a file of records, creating it if it doesn't exist.
+    pub fn append_to(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // Records are separated by a blank line
+        if file.metadata()?.len() > 0 {
+            writeln!(file)?;
+        }
+        write!(file, "{self}")
+    }
+
+    /// Reads every record in a file.
+    pub fn load_all(path: impl AsRef<Path>) -> Result<Vec<GameRecord>, RecordError> {
+        GameRecord::parse_all(&fs::read_to_string(path)?)
+    }
+
+    /// Parses any number of records, each starting with its headers.
+    pub fn parse_all(text: &str) -> Result<Vec<GameRecord>, RecordError> {
+        let mut games = vec![];
+        let mut current = String::new();
+        let mut in_moves = false;
+        for line in text.lines() {
+            let is_header = line.trim_start().starts_with('[');
+            if is_header && in_moves {
+                games.push(current.parse()?);
+                current.clear();
+                in_moves = false;
+            }
+            in_moves |= !is_header && !line.trim().is_empty();
+            current.push_str(line);
+            current.push('\n');
+        }
+        if !current.trim().is_empty() {
+            games.push(current.parse()?);
+        }
+        Ok(games)
+    }
+}
+
+impl Display for GameRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.headers.iter().filter(|(name, _)| name != "FEN") {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{name} \"{value}\"]")?;
+        }
+        if self.start != Board::default() {
+            writeln!(f, "[FEN \"{}\"]", self.start.to_fen())?;
+        }
+        writeln!(f)?;
+        let mut tokens = vec![];
+        let mut white_to_move = self.start.white_to_move;
+        for (i, mov) in self.moves.iter().enumerate() {
+            let number = (self.start.ply + i) / 2 + 1;
+            if white_to_move {
+                tokens.push(format!("{number}."));
+            } else if i == 0 {
+                tokens.push(format!("{number}..."));
+            }
+            tokens.push(mov.notation());
+            white_to_move = !white_to_move;
+        }
+        tokens.push(self.header("Result").unwrap_or("*").to_string());
+        // Keep lines to a readable length
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length > 0 && line_length + token.len() + 1 > 80 {
+                writeln!(f)?;
+                line_length = 0;
+            }
+            if line_length > 0 {
+                f.write_str(" ")?;
+                line_length += 1;
+            }
+            f.write_str(&token)?;
+            line_length += token.len();
+        }
+        writeln!(f)
+    }
+}
+
+impl FromStr for GameRecord {
+    type Err = RecordError;
+
+    /// Parses a single record, checking that every move is legal.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut record = GameRecord::default();
+        let mut movetext = String::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with('[') {
+                let header = || RecordError::Header(line.to_string());
+                let inner = line
+                    .strip_prefix('[')
+                    .and_then(|line| line.strip_suffix(']'))
+                    .ok_or_else(header)?;
+                let (name, value) = inner.split_once(' ').ok_or_else(header)?;
+                let value = value
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .and_then(unescape)
+                    .ok_or_else(header)?;
+                if name == "FEN" {
+                    record.start = value.parse().map_err(RecordError::Fen)?;
+                } else {
+                    record.set_header(name, &value);
+                }
+            } else {
+                movetext.push_str(line);
+                movetext.push(' ');
+            }
+        }
+        for token in movetext.split_whitespace() {
+            match token {
+                // Move numbers
+                _ if token.ends_with('.') && token.starts_with(|c: char| c.is_ascii_digit()) => {}
+                "1-0" | "0-1" | "*" => {
+                    if record.header("Result").is_none() {
+                        record.set_header("Result", token);
+                    }
+                }
+                _ => {
+                    let mov = token
+                        .parse()
+                        .map_err(|error| RecordError::Notation(record.moves.len(), error))?;
+                    record.moves.push(mov);
+                }
+            }
+        }
+        record.validate()?;
+        Ok(record)
+    }
+}
+
+/// Undoes the escaping of `"` and `\` in a header value, or `None` if a `\` isn't followed by
+/// anything.
+fn unescape(value: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' { chars.next()? } else { c });
+    }
+    Some(unescaped)
+}
+
+/// Today's date in UTC, as `YYYY.MM.DD`.
+fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    format!("{year:04}.{month:02}.{day:02}")
+}
+
+/// Converts a number of days since 1970-01-01 into a year, month and day, using Howard Hinnant's
+/// algorithm from http://howardhinnant.github.io/date_algorithms.html.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{rngs::StdRng, seq::IteratorRandom, SeedableRng};
+
+    fn random_game(seed: u64, start: Board) -> GameRecord {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut record = GameRecord::new(start.clone());
+        let mut board = start;
+        while let Some(mov) = board.legal_moves().choose(&mut rng) {
+            board.apply_move(&mov);
+            record.push(mov).unwrap();
+        }
+        record.set_header("White", "Random \"one\"");
+        record.set_header("Site", "C:\\Games\\");
+        record.set_header("Black", "Random two");
+        record.set_result_from_board().unwrap();
+        record
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let record = random_game(0, Board::default());
+        let text = record.to_string();
+        assert!(text.starts_with("[Date \""));
+        assert!(text.contains("\n\n1. "));
+        assert!(text.lines().all(|line| line.len() <= 80));
+        let parsed: GameRecord = text.parse().unwrap();
+        assert_eq!(parsed.moves, record.moves);
+        assert_eq!(parsed.header("White"), Some("Random \"one\""));
+        assert_eq!(parsed, record);
+        assert_eq!(parsed.header("Result"), record.header("Result"));
+        assert_ne!(parsed.header("Result"), Some("*"));
+    }
+
+    #[test]
+    fn test_fen_start_and_replay() {
+        let start: Board = "3B2B3/10/10/B8B/3x6/10/W8W/10/10/3W2W3 b 1"
+            .parse()
+            .unwrap();
+        let record = random_game(1, start.clone());
+        let text = record.to_string();
+        assert!(text.contains("\n1... "));
+        let parsed: GameRecord = text.parse().unwrap();
+        assert_eq!(parsed.start, start);
+        assert_eq!(parsed.replay(0).unwrap(), start);
+        let mut board = start;
+        board.apply_move(&record.moves[0]);
+        board.apply_move(&record.moves[1]);
+        assert_eq!(parsed.replay(2).unwrap(), board);
+        assert_eq!(
+            parsed.replay(usize::MAX).unwrap(),
+            parsed.validate().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_multiple_games() {
+        let games = [
+            random_game(2, Board::default()),
+            random_game(3, Board::default()),
+        ];
+        let text = format!("{}\n{}", games[0], games[1]);
+        let parsed = GameRecord::parse_all(&text).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].moves, games[0].moves);
+        assert_eq!(parsed[1].moves, games[1].moves);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            "[White amazons]\n\n*".parse::<GameRecord>(),
+            Err(RecordError::Header(_))
+        ));
+        assert!(matches!(
+            "1. a4-a5/a6 a5-a6/a7 *".parse::<GameRecord>(),
+            Err(RecordError::Illegal(1, _, MoveError::WrongTurn(_)))
+        ));
+        assert!(matches!(
+            "1. a4-a5 *".parse::<GameRecord>(),
+            Err(RecordError::Notation(0, ParseError::Format(_)))
+        ));
+        let mut record = random_game(4, Board::default());
+        let winner = record.header("Result").unwrap().to_string();
+        record.set_header("Result", if winner == "1-0" { "0-1" } else { "1-0" });
+        assert!(matches!(
+            record.to_string().parse::<GameRecord>(),
+            Err(RecordError::Result { .. })
+        ));
+        assert!(matches!(
+            record.push(record.moves[0]),
+            Err(RecordError::Illegal(..))
+        ));
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+}
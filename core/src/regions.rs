@@ -0,0 +1,273 @@
+//! Splitting the board into regions that the amazons can no longer move between.
+//!
+//! As arrows pile up the board breaks into separate areas. Once an area only holds amazons of one
+//! colour, the other player can never take any of it, so the game comes down to counting how many
+//! moves each player has left in the areas they own.
+
+use crate::{mask_coords, Board, Coord, PrettyCoord, TileState};
+use std::fmt::{self, Display, Formatter};
+
+/// Every square on the board.
+const BOARD_MASK: u128 = (1 << 100) - 1;
+
+/// The squares in column `col`.
+const fn column_mask(col: usize) -> u128 {
+    let mut mask = 0;
+    let mut row = 0;
+    while row < 10 {
+        mask |= 1 << (row * 10 + col);
+        row += 1;
+    }
+    mask
+}
+
+const COLUMN_A: u128 = column_mask(0);
+const COLUMN_J: u128 = column_mask(9);
+
+/// `mask` along with every square a king's move away from it.
+pub(crate) fn king_dilate(mask: u128) -> u128 {
+    let row = mask | ((mask & !COLUMN_J) << 1) | ((mask & !COLUMN_A) >> 1);
+    (row | (row << 10) | (row >> 10)) & BOARD_MASK
+}
+
+/// Who a region belongs to, judged by the amazons in it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Owner {
+    /// Only white amazons are in the region, so every move in it is white's.
+    White,
+    /// Only black amazons are in the region.
+    Black,
+    /// Amazons of both colours are in the region and are still fighting over it.
+    Contested,
+    /// There are no amazons in the region, so nobody can ever move there.
+    Dead,
+}
+
+impl Display for Owner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Owner::White => "white",
+            Owner::Black => "black",
+            Owner::Contested => "contested",
+            Owner::Dead => "dead",
+        })
+    }
+}
+
+/// A set of squares connected by king moves without crossing an arrow. Amazons can only ever move
+/// or fire within the region they are in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Region {
+    /// The empty squares in the region, as a mask
+    pub squares: u128,
+    /// The squares of the amazons in the region, in ascending order
+    pub amazons: Vec<Coord>,
+    pub owner: Owner,
+}
+
+impl Region {
+    /// The number of empty squares in the region.
+    pub fn size(&self) -> usize {
+        self.squares.count_ones() as usize
+    }
+
+    /// Whether only one player, or nobody, can ever move in the region.
+    pub fn is_settled(&self) -> bool {
+        self.owner != Owner::Contested
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let size = self.size();
+        let plural = if size == 1 { "" } else { "s" };
+        write!(f, "{} region of {size} square{plural}", self.owner)?;
+        if !self.amazons.is_empty() {
+            let amazons: Vec<String> = self
+                .amazons
+                .iter()
+                .map(|&amazon| PrettyCoord::from(amazon).to_string())
+                .collect();
+            write!(f, " with amazons on {}", amazons.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Board {
+    /// Splits the squares that aren't arrows into [`Region`]s, ordered by their lowest square.
+    /// Regions without any empty squares are included when they hold an amazon, since that amazon
+    /// is trapped.
+    pub fn regions(&self) -> Vec<Region> {
+        let arrows = self.mask_of(TileState::Arrow);
+        let white = self.mask_of(TileState::White);
+        let black = self.mask_of(TileState::Black);
+        let mut unvisited = BOARD_MASK & !arrows;
+        let mut regions = vec![];
+        while unvisited != 0 {
+            let mut region = 1 << unvisited.trailing_zeros();
+            loop {
+                let grown = king_dilate(region) & unvisited;
+                if grown == region {
+                    break;
+                }
+                region = grown;
+            }
+            unvisited &= !region;
+            let owner = match (region & white != 0, region & black != 0) {
+                (true, true) => Owner::Contested,
+                (true, false) => Owner::White,
+                (false, true) => Owner::Black,
+                (false, false) => Owner::Dead,
+            };
+            regions.push(Region {
+                squares: region & !(white | black),
+                amazons: mask_coords(region & (white | black)).collect(),
+                owner,
+            });
+        }
+        regions
+    }
+
+    /// Whether every amazon is in a region of its own colour, so that neither player can get in
+    /// the other's way any more and the winner is whoever can make more moves in their own
+    /// regions.
+    ///
+    /// This is cheap enough to check at every node of a search: it grows the white amazons'
+    /// regions without building any [`Region`]s, and stops as soon as one reaches a black amazon.
+    pub fn is_partitioned(&self) -> bool {
+        let amazon_mask = |pieces: &[Coord]| pieces.iter().fold(0u128, |mask, &c| mask | 1 << c);
+        let white = amazon_mask(&self.pieces[0..4]);
+        let black = amazon_mask(&self.pieces[4..8]);
+        let open = BOARD_MASK & !(self.collisions & !(white | black));
+        let mut reached = white;
+        loop {
+            if reached & black != 0 {
+                return false;
+            }
+            let grown = king_dilate(reached) & open;
+            if grown == reached {
+                return true;
+            }
+            reached = grown;
+        }
+    }
+
+    fn mask_of(&self, state: TileState) -> u128 {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, &tile)| tile == state)
+            .fold(0, |mask, (coord, _)| mask | 1 << coord)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::c;
+
+    #[test]
+    fn test_start_position() {
+        let regions = Board::default().regions();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].owner, Owner::Contested);
+        assert_eq!(regions[0].size(), 92);
+        assert_eq!(regions[0].amazons.len(), 8);
+        assert!(!Board::default().is_partitioned());
+    }
+
+    #[test]
+    fn test_partitioned() {
+        // A wall of arrows along row 5 splits the board in two, apart from f6 which only joins the
+        // top half. j1 is walled off with no amazon, and the white amazon on a4 is boxed in.
+        let board: Board = "3B2B3/10/10/B8B/xxxxx1xxxx/xxxxxxxxxx/Wx7W/xx8/8xx/3W2W1x1 w 30"
+            .parse()
+            .unwrap();
+        let regions = board.regions();
+        assert_eq!(
+            regions,
+            [
+                Region {
+                    squares: regions[0].squares,
+                    amazons: vec![c!(d1), c!(g1), c!(j4)],
+                    owner: Owner::White,
+                },
+                Region {
+                    squares: 1 << c!(j1),
+                    amazons: vec![],
+                    owner: Owner::Dead,
+                },
+                Region {
+                    squares: 0,
+                    amazons: vec![c!(a4)],
+                    owner: Owner::White,
+                },
+                Region {
+                    squares: regions[3].squares,
+                    amazons: vec![c!(a7), c!(j7), c!(d10), c!(g10)],
+                    owner: Owner::Black,
+                },
+            ]
+        );
+        let sizes: Vec<usize> = regions.iter().map(Region::size).collect();
+        assert_eq!(sizes, [29, 1, 0, 37]);
+        assert!(regions[3].squares & 1 << c!(f6) != 0);
+        assert!(board.is_partitioned());
+        assert_eq!(
+            regions[0].to_string(),
+            "white region of 29 squares with amazons on d1, g1, j4"
+        );
+        assert_eq!(regions[1].to_string(), "dead region of 1 square");
+    }
+
+    #[test]
+    fn test_diagonal_gap() {
+        // The same wall with a gap at e5, which touches f6 diagonally, so the halves are joined
+        let board: Board = "3B2B3/10/10/B8B/xxxxx1xxxx/xxxx1xxxxx/Wx7W/xx8/8xx/3W2W1x1 w 30"
+            .parse()
+            .unwrap();
+        let summary: Vec<(Owner, usize, usize)> = board
+            .regions()
+            .iter()
+            .map(|region| (region.owner, region.size(), region.amazons.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Owner::Contested, 67, 7),
+                (Owner::Dead, 1, 0),
+                (Owner::White, 0, 1),
+            ]
+        );
+        assert!(!board.is_partitioned());
+    }
+
+    #[test]
+    fn test_is_partitioned_matches_regions() {
+        let mut player = crate::RandomPlayer::new(7);
+        for _ in 0..20 {
+            let mut board = Board::default();
+            while let Some(mov) = player.choose_move(&board) {
+                assert_eq!(
+                    board.is_partitioned(),
+                    board.regions().iter().all(Region::is_settled),
+                    "{board}"
+                );
+                board.try_apply_move(&mov).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_king_dilate() {
+        assert_eq!(
+            mask_coords(king_dilate(1 << c!(a1))).collect::<Vec<_>>(),
+            [c!(a1), c!(b1), c!(a2), c!(b2)]
+        );
+        // Nothing wraps around from one side of the board to the other
+        assert_eq!(king_dilate(1 << c!(j5)).count_ones(), 6);
+        assert_eq!(king_dilate(1 << c!(a10)).count_ones(), 4);
+        assert_eq!(king_dilate(BOARD_MASK), BOARD_MASK);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{Move, MMT};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+/// How the score stored in an [`Entry`] relates to the true score of the position.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bound {
+    /// The score is exact.
+    Exact,
+    /// The search failed high, the true score is at least this large.
+    Lower,
+    /// The search failed low, the true score is at most this large.
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub hash: u64,
+    pub depth: usize,
+    pub bound: Bound,
+    pub score: MMT,
+    pub best_move: Option<Move>,
+    // Which search stored this entry, so stale entries can be replaced by shallower ones
+    age: usize,
+}
+
+impl Entry {
+    pub fn new(hash: u64, depth: usize, bound: Bound, score: MMT, best_move: Option<Move>) -> Self {
+        Self {
+            hash,
+            depth,
+            bound,
+            score,
+            best_move,
+            age: 0,
+        }
+    }
+}
+
+/// A fixed size hash table of previously searched positions, indexed by Zobrist hash.
+///
+/// When two positions map to the same slot, the one searched to a greater depth is kept, unless
+/// the existing entry is left over from an earlier search.
+///
+/// The table can be shared between threads searching the same position. Entries are packed into
+/// atomics without locking, and the slot's key is stored XORed with its contents, so an entry
+/// that is torn by two threads writing to it at once just looks like a miss.
+pub struct TranspositionTable {
+    slots: Vec<[AtomicU64; 3]>,
+    age: usize,
+}
+
+/// Set in the packed entry of every slot that has been stored to, so an empty slot never matches
+/// a position whose hash happens to be zero.
+const OCCUPIED: u64 = 1 << 10;
+
+/// Only the low bits of the age are stored, it just needs to tell this search from recent ones.
+const AGE_MASK: usize = 0x7fff_ffff;
+
+impl Entry {
+    /// Packs everything other than the hash and score into one word.
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        let mov = self.best_move.map_or(0, |Move(piece, to, arrow)| {
+            1 | (piece as u64) << 1 | (to as u64) << 8 | (arrow as u64) << 15
+        });
+        self.depth.min(255) as u64
+            | bound << 8
+            | OCCUPIED
+            | mov << 11
+            | ((self.age & AGE_MASK) as u64) << 33
+    }
+
+    fn unpack(hash: u64, score: u64, packed: u64) -> Self {
+        let mov = packed >> 11;
+        let coord = |shift: u64| (mov >> shift & 0x7f) as usize;
+        Self {
+            hash,
+            depth: (packed & 0xff) as usize,
+            bound: match packed >> 8 & 0b11 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            score: MMT::from_bits(score),
+            best_move: (mov & 1 == 1).then(|| Move(coord(1), coord(8), coord(15))),
+            age: (packed >> 33) as usize,
+        }
+    }
+}
+
+impl TranspositionTable {
+    /// Creates a table with room for `len` entries. A table with no entries is valid, it just never
+    /// remembers anything.
+    pub fn new(len: usize) -> Self {
+        Self {
+            slots: (0..len).map(|_| Default::default()).collect(),
+            age: 0,
+        }
+    }
+
+    /// Creates the largest table that fits in `megabytes` of memory.
+    pub fn with_size_mb(megabytes: usize) -> Self {
+        Self::new(megabytes * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 3]>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Marks every existing entry as stale. Call this before starting to search a new position.
+    pub fn new_search(&mut self) {
+        self.age += 1;
+    }
+
+    pub fn clear(&mut self) {
+        for slot in self.slots.iter_mut() {
+            for word in slot.iter_mut() {
+                *word.get_mut() = 0;
+            }
+        }
+        self.new_search();
+    }
+
+    fn slot(&self, hash: u64) -> &[AtomicU64; 3] {
+        &self.slots[(hash % self.slots.len() as u64) as usize]
+    }
+
+    /// Whatever is in the slot for `hash`, which may be for another position.
+    fn read(&self, hash: u64) -> Option<Entry> {
+        let [key, score, packed] = self.slot(hash).each_ref().map(|word| word.load(Relaxed));
+        (packed & OCCUPIED != 0).then(|| Entry::unpack(key ^ score ^ packed, score, packed))
+    }
+
+    /// The entry for `hash`, if there is one. Hit statistics are kept by the caller, in
+    /// [`SearchContext`](crate::SearchContext), so that threads sharing the table don't contend
+    /// over a counter on every probe.
+    pub fn probe(&self, hash: u64) -> Option<Entry> {
+        if self.is_empty() {
+            return None;
+        }
+        self.read(hash).filter(|entry| entry.hash == hash)
+    }
+
+    pub fn store(&self, entry: Entry) {
+        if self.is_empty() {
+            return;
+        }
+        let entry = Entry {
+            age: self.age & AGE_MASK,
+            ..entry
+        };
+        match self.read(entry.hash) {
+            Some(existing) if existing.age == entry.age && existing.depth > entry.depth => {}
+            _ => {
+                let score = entry.score.to_bits();
+                let packed = entry.pack();
+                let [key_word, score_word, packed_word] = self.slot(entry.hash);
+                key_word.store(entry.hash ^ score ^ packed, Relaxed);
+                score_word.store(score, Relaxed);
+                packed_word.store(packed, Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::WIN;
+
+    #[test]
+    fn test_replace_by_depth() {
+        let mut tt = TranspositionTable::new(16);
+        tt.store(Entry::new(1, 3, Bound::Exact, 1.0, None));
+        // Same slot, shallower search
+        tt.store(Entry::new(17, 2, Bound::Exact, 2.0, None));
+        assert_eq!(tt.probe(1).map(|entry| entry.score), Some(1.0));
+        assert!(tt.probe(17).is_none());
+        // Same slot, deeper search
+        tt.store(Entry::new(17, 4, Bound::Lower, 3.0, None));
+        assert!(tt.probe(1).is_none());
+        assert_eq!(tt.probe(17).map(|entry| entry.bound), Some(Bound::Lower));
+        // Entries from earlier searches can always be replaced
+        tt.new_search();
+        tt.store(Entry::new(1, 0, Bound::Upper, 4.0, None));
+        assert_eq!(tt.probe(1).map(|entry| entry.score), Some(4.0));
+    }
+
+    #[test]
+    fn test_packing() {
+        let tt = TranspositionTable::new(16);
+        for (hash, depth, bound, score, best_move) in [
+            (u64::MAX, 0, Bound::Exact, 0.0, None),
+            (
+                u64::MAX - 1,
+                92,
+                Bound::Lower,
+                -WIN - 3.0,
+                Some(Move(99, 0, 98)),
+            ),
+            (u64::MAX - 2, 7, Bound::Upper, 1.25, Some(Move(3, 33, 63))),
+        ] {
+            tt.store(Entry::new(hash, depth, bound, score, best_move));
+            let entry = tt.probe(hash).unwrap();
+            assert_eq!(entry.depth, depth);
+            assert_eq!(entry.bound, bound);
+            assert_eq!(entry.score, score);
+            assert_eq!(entry.best_move, best_move);
+        }
+        // An empty slot doesn't match a hash of zero
+        assert!(tt.probe(0).is_none());
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let tt = TranspositionTable::new(64);
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let tt = &tt;
+                scope.spawn(move || {
+                    for i in 0..10_000u64 {
+                        let hash = i.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ thread;
+                        tt.store(Entry::new(hash, 1, Bound::Exact, hash as MMT, None));
+                    }
+                });
+            }
+        });
+        // Whatever survived must be intact, not a mix of two entries
+        for slot in 0..64 {
+            if let Some(entry) = tt.read(slot) {
+                if let Some(entry) = tt.probe(entry.hash) {
+                    assert_eq!(entry.score, entry.hash as MMT);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_table() {
+        let tt = TranspositionTable::new(0);
+        tt.store(Entry::new(1, 3, Bound::Exact, 1.0, None));
+        assert!(tt.probe(1).is_none());
+    }
+}
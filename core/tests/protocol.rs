@@ -0,0 +1,212 @@
+//! Plays through protocol sessions with the engine binary.
+
+use amazons_core::{
+    protocol::{Command, Response},
+    Board, GameRecord, Move,
+};
+use std::io::{BufRead, BufReader, Lines, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command as Process, Stdio};
+use std::time::{Duration, Instant};
+
+struct Session {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl Session {
+    fn start(args: &[&str]) -> Self {
+        let mut child = Process::new(env!("CARGO_BIN_EXE_amazons_core"))
+            .args(args)
+            .env_remove("AMAZONS_LOG")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Failed to start the engine");
+        Self {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()).lines(),
+            child,
+        }
+    }
+
+    fn send(&mut self, command: Command) {
+        writeln!(self.stdin, "{command}").unwrap();
+    }
+
+    fn receive(&mut self) -> Response {
+        let line = self.stdout.next().expect("Engine closed stdout").unwrap();
+        line.parse()
+            .unwrap_or_else(|err| panic!("Engine sent {line:?}: {err}"))
+    }
+
+    /// Reads responses until the best move, skipping any `info`.
+    fn best_move(&mut self) -> Option<Move> {
+        loop {
+            match self.receive() {
+                Response::BestMove(mov) => return mov,
+                Response::Info(_) => {}
+                response => panic!("Expected bestmove, got {response:?}"),
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn test_handshake_and_search() {
+    let mut session = Session::start(&[]);
+    session.send(Command::Amazons);
+    let mut ids = 0;
+    loop {
+        match session.receive() {
+            Response::Id { .. } => ids += 1,
+            Response::Option { .. } => {}
+            Response::AmazonsOk => break,
+            response => panic!("Unexpected response to amazons: {response:?}"),
+        }
+    }
+    assert!(ids > 0);
+    session.send(Command::IsReady);
+    assert_eq!(session.receive(), Response::ReadyOk);
+
+    let first: Move = "d1-d7/g7".parse().unwrap();
+    session.send(Command::NewGame);
+    session.send(Command::Position {
+        start: Board::default(),
+        moves: vec![first],
+    });
+    session.send("go depth 1".parse().unwrap());
+    let reply = session.best_move().expect("Black has moves");
+    let mut board = Board::default();
+    board.try_apply_move(&first).unwrap();
+    assert!(board.is_legal(&reply), "{reply} is not legal");
+
+    session.send(Command::Quit);
+    assert!(session.child.wait().unwrap().success());
+}
+
+#[test]
+fn test_stop() {
+    let mut session = Session::start(&[]);
+    session.send("go infinite".parse().unwrap());
+    std::thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    session.send(Command::Stop);
+    let mov = session.best_move().expect("White has moves");
+    assert!(Board::default().is_legal(&mov));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_go_without_own_clock() {
+    // Neither an increment alone nor the opponent's clock should leave the search unbounded
+    let mut session = Session::start(&["--move-time", "200"]);
+    let first: Move = "d1-d7/g7".parse().unwrap();
+    let mut board = Board::default();
+    board.try_apply_move(&first).unwrap();
+    for go in ["go winc 100", "go wtime 60000 winc 100"] {
+        session.send(Command::Position {
+            start: Board::default(),
+            moves: vec![first],
+        });
+        let start = Instant::now();
+        session.send(go.parse().unwrap());
+        let reply = session.best_move().expect("Black has moves");
+        assert!(board.is_legal(&reply), "{reply} is not legal");
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "{go} took too long"
+        );
+    }
+}
+
+#[test]
+fn test_mcts() {
+    let mut session = Session::start(&["--search", "mcts"]);
+    let first: Move = "d1-d7/g7".parse().unwrap();
+    let mut board = Board::default();
+    board.try_apply_move(&first).unwrap();
+    for _ in 0..2 {
+        session.send(Command::Position {
+            start: Board::default(),
+            moves: vec![first],
+        });
+        session.send("go nodes 500".parse().unwrap());
+        let reply = session.best_move().expect("Black has moves");
+        assert!(board.is_legal(&reply), "{reply} is not legal");
+    }
+}
+
+#[test]
+fn test_logging() {
+    let mut session = Session::start(&["--log", "info"]);
+    session.send("go depth 2".parse().unwrap());
+    let mut depths = vec![];
+    loop {
+        match session.receive() {
+            Response::Info(info) => {
+                if let Some(rest) = info.strip_prefix("depth ") {
+                    depths.push(rest.split(' ').next().unwrap().to_string());
+                    assert!(info.contains(" pv "), "{info}");
+                }
+            }
+            Response::BestMove(mov) => {
+                assert!(mov.is_some());
+                break;
+            }
+            response => panic!("Unexpected response to go: {response:?}"),
+        }
+    }
+    assert_eq!(depths, ["1", "2"]);
+
+    // Nothing is logged by default
+    let mut session = Session::start(&[]);
+    session.send("go depth 2".parse().unwrap());
+    assert!(matches!(session.receive(), Response::BestMove(Some(_))));
+}
+
+#[test]
+fn test_record() {
+    let path = std::env::temp_dir().join(format!("amazons-record-{}.txt", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut session = Session::start(&["--record", path.to_str().unwrap()]);
+    let moves: Vec<Move> = vec!["d1-d7/g7".parse().unwrap(), "g10-g8/e6".parse().unwrap()];
+    session.send(Command::Position {
+        start: Board::default(),
+        moves: moves.clone(),
+    });
+    session.send(Command::NewGame);
+
+    // White's only move leaves black stuck, and the GUI never sends it back before quitting
+    let start: Board = "BxBxBxBxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/\
+                        xxxxxxxxxx/xxxxxxxxxx/xxxxxxxxxx/W1xWxWxWxx w 50"
+        .parse()
+        .unwrap();
+    session.send(Command::Position {
+        start,
+        moves: vec![],
+    });
+    session.send("go depth 1".parse().unwrap());
+    let last = session.best_move().expect("White has a move");
+    session.send(Command::Quit);
+    assert!(session.child.wait().unwrap().success());
+
+    let records = GameRecord::load_all(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].moves, moves);
+    assert_eq!(records[0].header("Result"), Some("*"));
+    assert_eq!(records[0].header("White"), None);
+    assert_eq!(records[1].moves, [last]);
+    assert_eq!(records[1].header("White"), Some("amazons_core"));
+    assert_eq!(records[1].header("Black"), Some("Opponent"));
+    assert_eq!(records[1].header("Result"), Some("1-0"));
+}
@@ -4,16 +4,20 @@ extends Node2D
 enum SquareState {WHITE, BLACK, ARROW, EMPTY}
 
 @onready var board_squares = $BoardSquares
+@onready var board = $Board
 @onready var black_cli = $BlackCli
 @onready var white_cli = $WhiteCli
+@onready var black_engine = $BlackEngine
+@onready var white_engine = $WhiteEngine
+@onready var engine_log = $EngineLog
 var square_scene = preload("res://Square.tscn")
 var squares = {}
 
-var whites_turn: bool = true
-
-enum PlayerType {UI, CLI}
+enum PlayerType {UI, CLI, ENGINE}
 var white_player_type: PlayerType = PlayerType.UI
 var black_player_type: PlayerType = PlayerType.UI
+# Whether to tint the squares each player has sealed off for themselves
+var show_territory: bool = false
 
 func _ready():
 	for x in range(0, 10):
@@ -27,14 +31,49 @@ func _ready():
 			squares[x][y] = square
 			square.click.connect(_on_click)
 			square.cancel.connect(_on_cancel)
-	if black_cli.start_black():
+	var args = OS.get_cmdline_user_args()
+	if "--black-engine" in args:
+		black_player_type = PlayerType.ENGINE
+	elif black_cli.start_black():
 		black_player_type = PlayerType.CLI
-	if white_cli.start_white():
+	if "--white-engine" in args:
+		white_player_type = PlayerType.ENGINE
+	elif white_cli.start_white():
 		white_player_type = PlayerType.CLI
+	black_engine.move_ready.connect(_on_ai_move.bind(false))
+	white_engine.move_ready.connect(_on_ai_move.bind(true))
+	black_cli.move_ready.connect(_on_ai_move.bind(false))
+	white_cli.move_ready.connect(_on_ai_move.bind(true))
+	black_cli.engine_error.connect(_on_cli_error)
+	white_cli.engine_error.connect(_on_cli_error)
+	black_engine.engine_error.connect(_on_cli_error)
+	white_engine.engine_error.connect(_on_cli_error)
+	black_cli.log_line.connect(_on_cli_log_line.bind("black"))
+	white_cli.log_line.connect(_on_cli_log_line.bind("white"))
 	_new_game()
 	check_for_cli_move()
 
+func _unhandled_key_input(event):
+	if not event.pressed:
+		return
+	if event.keycode == KEY_N:
+		# Start over, restarting any CLI players in case they have crashed
+		reset_move_state()
+		for cli in [black_cli, white_cli]:
+			if cli.is_enabled():
+				cli.restart()
+		_new_game()
+		check_for_cli_move()
+	elif event.keycode == KEY_L:
+		engine_log.visible = not engine_log.visible
+	elif event.keycode == KEY_T:
+		show_territory = not show_territory
+		update_territory()
+
 func _new_game():
+	board.new_game()
+	black_engine.new_game()
+	white_engine.new_game()
 	for x in range(0, 10):
 		for y in range(0, 10):
 			squares[x][y].mark_empty()
@@ -46,72 +85,126 @@ func _new_game():
 	squares[3][9].mark_black()
 	squares[6][9].mark_black()
 	squares[9][6].mark_black()
+	update_territory()
+
+func whites_turn() -> bool:
+	return board.side_to_move() == AmazonsBoard.WHITE
 
 func _on_click(x: int, y: int, state: SquareState):
-	if (whites_turn and white_player_type == PlayerType.UI) or ((not whites_turn) and black_player_type == PlayerType.UI):
+	if (whites_turn() and white_player_type == PlayerType.UI) or ((not whites_turn()) and black_player_type == PlayerType.UI):
 		handle_input(x, y, state)
 
 var piece: Array[int] = []
 var move: Array[int] = []
-func handle_input(x: int, y: int, state: SquareState):
+func handle_input(x: int, y: int, _state: SquareState):
 	if piece == []:
 		# Selecting a piece
-		if (whites_turn and state == SquareState.WHITE) or ((not whites_turn) and state == SquareState.BLACK):
+		var destinations = board.legal_destinations(x, y)
+		if not destinations.is_empty():
 			piece = [x, y]
+			highlight(destinations)
 			return
 		else:
 			reset_move_state()
 	elif move == []:
 		# Selecting a move for a piece
-		if state == SquareState.EMPTY:
-			# TODO check for legal move
+		if is_highlighted(x, y):
 			move = [x, y]
 			# Move the piece temporarily for visualization
-			if whites_turn:
+			if whites_turn():
 				squares[x][y].mark_white()
 			else:
 				squares[x][y].mark_black()
 			squares[piece[0]][piece[1]].mark_empty()
+			highlight(board.legal_arrow_targets(piece, move))
 			return
 		else:
 			reset_move_state()
 	else:
 		# Selecting an arrow
-		if state == SquareState.EMPTY:
-			# TODO check for legal move
-			squares[x][y].mark_arrow()
-			if whites_turn:
-				black_cli.notify_of_move(piece, move, Array([x,y], TYPE_INT, &"", null))
-			else:
-				white_cli.notify_of_move(piece, move, Array([x,y], TYPE_INT, &"", null))
-			whites_turn = not whites_turn
+		if is_highlighted(x, y):
+			var arrow: Array[int] = [x, y]
+			var move_piece = piece
+			var move_to = move
 			piece = []
 			move = []
-			check_for_cli_move()
+			highlight([])
+			play_move(move_piece, move_to, arrow)
 		else:
 			reset_move_state()
 
 func check_for_cli_move():
-	if whites_turn and white_player_type == PlayerType.CLI:
-		var packed_move = white_cli.get_move()
-		var piece = packed_move[0]
-		var move = packed_move[1]
-		var arrow = packed_move[2]
-		squares[piece[0]][piece[1]].mark_empty()
+	# The engine thinks in the background and tells us when it's done. CLI players send their
+	# moves whenever they are ready, without being asked.
+	if whites_turn() and white_player_type == PlayerType.ENGINE:
+		white_engine.start_thinking()
+	elif (not whites_turn()) and black_player_type == PlayerType.ENGINE:
+		black_engine.start_thinking()
+	elif whites_turn() and white_player_type == PlayerType.CLI:
+		white_cli.expect_move()
+	elif (not whites_turn()) and black_player_type == PlayerType.CLI:
+		black_cli.expect_move()
+
+func _on_ai_move(piece, move, arrow, is_white: bool):
+	if is_white != whites_turn():
+		push_error("AI tried to move when it wasn't its turn")
+		return
+	if not play_move(piece, move, arrow):
+		push_error("AI played an illegal move")
+
+func _on_cli_error(message: String):
+	push_error(message)
+	engine_log.append_text("[color=red]%s[/color]\n" % message)
+
+func _on_cli_log_line(line: String, color: String):
+	engine_log.append_text("%s: %s\n" % [color, line])
+
+# Check a move against the rules, then display it, tell the other player about it and pass the turn
+func play_move(piece, move, arrow) -> bool:
+	var white = whites_turn()
+	if not board.apply_move(piece, move, arrow):
+		return false
+	squares[piece[0]][piece[1]].mark_empty()
+	if white:
 		squares[move[0]][move[1]].mark_white()
-		squares[arrow[0]][arrow[1]].mark_arrow()
-	elif (not whites_turn) and black_player_type == PlayerType.CLI:
-		var packed_move = black_cli.get_move()
-		var piece = packed_move[0]
-		var move = packed_move[1]
-		var arrow = packed_move[2]
-		squares[piece[0]][piece[1]].mark_empty()
+	else:
 		squares[move[0]][move[1]].mark_black()
-		squares[arrow[0]][arrow[1]].mark_arrow()
+	squares[arrow[0]][arrow[1]].mark_arrow()
+	update_territory()
+	# The CLIs keep the whole game, including their own moves once they've been accepted here. The
+	# engine that just moved already knows about its move, so only tell the other one
+	black_cli.notify_of_move(piece, move, arrow)
+	white_cli.notify_of_move(piece, move, arrow)
+	if white:
+		black_engine.notify_of_move(piece, move, arrow)
 	else:
-		return
-	whites_turn = not whites_turn
-	check_for_cli_move()
+		white_engine.notify_of_move(piece, move, arrow)
+	if board.is_game_over():
+		print("White wins" if white else "Black wins")
+	else:
+		check_for_cli_move()
+	return true
+
+# Tint every empty square by who owns it, or clear the tints if the overlay is hidden
+func update_territory():
+	var territory = board.territory()
+	for x in range(0, 10):
+		for y in range(0, 10):
+			if show_territory:
+				squares[x][y].set_territory(territory[x + 10 * y])
+			else:
+				squares[x][y].set_territory(AmazonsBoard.OCCUPIED)
+
+# Highlight exactly the given [x, y] squares
+func highlight(targets):
+	for x in range(0, 10):
+		for y in range(0, 10):
+			squares[x][y].set_highlighted(false)
+	for target in targets:
+		squares[target[0]][target[1]].set_highlighted(true)
+
+func is_highlighted(x: int, y: int) -> bool:
+	return squares[x][y].highlighted
 
 func _on_cancel():
 	reset_move_state()
@@ -120,9 +213,10 @@ func reset_move_state():
 	if move != []:
 		# Reset the temporary move
 		squares[move[0]][move[1]].mark_empty()
-		if whites_turn:
+		if whites_turn():
 			squares[piece[0]][piece[1]].mark_white()
 		else:
 			squares[piece[0]][piece[1]].mark_black()
 	piece = []
 	move = []
+	highlight([])
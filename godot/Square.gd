@@ -14,12 +14,25 @@ enum State {WHITE, BLACK, ARROW, EMPTY}
 var x: int
 var y: int
 var state: State = State.EMPTY
+# Whether the square is a legal choice for the move being entered
+var highlighted: bool = false
+# The player who owns the square, as returned by AmazonsBoard.territory(), or OCCUPIED to show no
+# owner
+var territory: int = AmazonsBoard.OCCUPIED
 
 func _update():
 	if (x+y) % 2 == 0:
 		background.color = Color(0.9, 0.9, 0.9)
 	else:
 		background.color = Color(0.4, 0.4, 0.4)
+	if territory == AmazonsBoard.WHITE:
+		background.color = background.color.lerp(Color(0.9, 0.8, 0.4), 0.5)
+	elif territory == AmazonsBoard.BLACK:
+		background.color = background.color.lerp(Color(0.3, 0.4, 0.8), 0.5)
+	elif territory == AmazonsBoard.DEAD:
+		background.color = background.color.lerp(Color(0.7, 0.3, 0.3), 0.5)
+	if highlighted:
+		background.color = background.color.lerp(Color(0.3, 0.7, 0.3), 0.5)
 	if state == State.WHITE:
 		black.visible = false
 		white.visible = true
@@ -66,3 +79,11 @@ func mark_arrow():
 func mark_empty():
 	state = State.EMPTY
 	_update()
+
+func set_highlighted(value: bool):
+	highlighted = value
+	_update()
+
+func set_territory(value: int):
+	territory = value
+	_update()
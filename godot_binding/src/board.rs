@@ -0,0 +1,128 @@
+use crate::coords::{coord_from_xy, xy_from_coord};
+use crate::{array_from_coord, coord_from_array};
+use amazons_core::{mask_coords, Board, Coord, Move, Outcome, Owner};
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+
+/// The rules of the game, for the UI to check moves against. Every move played, by either side,
+/// must be passed to `apply_move` to keep this in sync with the scene.
+#[derive(GodotClass)]
+#[class(base=Node)]
+pub struct AmazonsBoard {
+    base: Base<Node>,
+
+    board: Board,
+}
+
+#[godot_api]
+impl INode for AmazonsBoard {
+    fn init(base: Base<Node>) -> Self {
+        Self {
+            base,
+            board: Board::default(),
+        }
+    }
+}
+
+#[godot_api]
+impl AmazonsBoard {
+    #[constant]
+    const WHITE: i64 = 0;
+    #[constant]
+    const BLACK: i64 = 1;
+    /// Returned by `territory` for squares that either player could still claim.
+    #[constant]
+    const CONTESTED: i64 = 2;
+    /// Returned by `territory` for squares that nobody can ever reach again.
+    #[constant]
+    const DEAD: i64 = 3;
+    /// Returned by `territory` for squares with an amazon or an arrow on them.
+    #[constant]
+    const OCCUPIED: i64 = -1;
+
+    #[func]
+    fn new_game(&mut self) {
+        self.board = Board::default();
+    }
+
+    /// The `[x, y]` squares the amazon at `(x, y)` can move to. Empty if there isn't an amazon
+    /// belonging to the player to move there.
+    #[func]
+    fn legal_destinations(&self, x: i64, y: i64) -> VariantArray {
+        let Some(piece) = coord_from_xy(x, y) else {
+            return VariantArray::new();
+        };
+        self.board
+            .legal_destinations(piece)
+            .map(variant_from_coord)
+            .collect()
+    }
+
+    /// The `[x, y]` squares an arrow can be fired at after moving the amazon at `from` to `to`.
+    /// Empty if that isn't a legal move for the amazon.
+    #[func]
+    fn legal_arrow_targets(&self, from: Array<i64>, to: Array<i64>) -> VariantArray {
+        let (Some(from), Some(to)) = (coord_from_array(&from), coord_from_array(&to)) else {
+            return VariantArray::new();
+        };
+        self.board
+            .legal_arrow_targets(from, to)
+            .map(variant_from_coord)
+            .collect()
+    }
+
+    /// Plays a move for the player to move. Returns false, leaving the board untouched, if it is
+    /// not legal.
+    #[func]
+    fn apply_move(&mut self, piece: Array<i64>, mov: Array<i64>, arrow: Array<i64>) -> bool {
+        let (Some(piece), Some(mov), Some(arrow)) = (
+            coord_from_array(&piece),
+            coord_from_array(&mov),
+            coord_from_array(&arrow),
+        ) else {
+            return false;
+        };
+        self.board.try_apply_move(&Move(piece, mov, arrow)).is_ok()
+    }
+
+    /// Either `WHITE` or `BLACK`.
+    #[func]
+    fn side_to_move(&self) -> i64 {
+        if self.board.white_to_move {
+            Self::WHITE
+        } else {
+            Self::BLACK
+        }
+    }
+
+    /// Who owns each square, indexed by `x + 10 * y`: `WHITE` or `BLACK` for empty squares in a
+    /// region that only that player's amazons are in, otherwise `CONTESTED`, `DEAD` or
+    /// `OCCUPIED`.
+    #[func]
+    fn territory(&self) -> Array<i64> {
+        let mut owners = [Self::OCCUPIED; 100];
+        for region in self.board.regions() {
+            let owner = match region.owner {
+                Owner::White => Self::WHITE,
+                Owner::Black => Self::BLACK,
+                Owner::Contested => Self::CONTESTED,
+                Owner::Dead => Self::DEAD,
+            };
+            for coord in mask_coords(region.squares) {
+                let (x, y) = xy_from_coord(coord);
+                owners[(x + 10 * y) as usize] = owner;
+            }
+        }
+        owners.into_iter().collect()
+    }
+
+    /// Whether the player to move has run out of moves, and so lost.
+    #[func]
+    fn is_game_over(&self) -> bool {
+        self.board.outcome() != Outcome::Ongoing
+    }
+}
+
+fn variant_from_coord(coord: Coord) -> Variant {
+    array_from_coord(coord).to_variant()
+}
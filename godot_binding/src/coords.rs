@@ -0,0 +1,64 @@
+//! Conversions between the `(x, y)` positions the Godot scene uses for squares and
+//! [`amazons_core::Coord`] indices. `x` is the column, from `0` for `a` to `9` for `j`, and `y` is
+//! the row, from `0` for `1` to `9` for `10`.
+//!
+//! These are kept free of Godot types so they can be tested without the engine.
+
+use amazons_core::{Coord, Dim, PrettyCoord};
+
+/// The core coordinate of the square at `(x, y)`, or `None` if that isn't on the board.
+pub fn coord_from_xy(x: i64, y: i64) -> Option<Coord> {
+    let x = usize::try_from(x).ok().filter(|x| *x < 10)?;
+    let y = usize::try_from(y).ok().filter(|y| *y < 10)?;
+    Some(Coord::from(&PrettyCoord(Dim::from(x), Dim::from(y))))
+}
+
+/// The `(x, y)` position of a core coordinate.
+pub fn xy_from_coord(coord: Coord) -> (i64, i64) {
+    let PrettyCoord(x, y) = PrettyCoord::from(coord);
+    (usize::from(&x) as i64, usize::from(&y) as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for x in 0..10 {
+            for y in 0..10 {
+                let coord = coord_from_xy(x, y).unwrap();
+                assert_eq!(xy_from_coord(coord), (x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_notation() {
+        // The scene's starting position, see Scene.gd
+        assert_eq!(
+            coord_from_xy(0, 3).map(PrettyCoord::from),
+            "a4".parse().ok()
+        );
+        assert_eq!(
+            coord_from_xy(3, 0).map(PrettyCoord::from),
+            "d1".parse().ok()
+        );
+        assert_eq!(
+            coord_from_xy(3, 9).map(PrettyCoord::from),
+            "d10".parse().ok()
+        );
+        assert_eq!(
+            coord_from_xy(9, 6).map(PrettyCoord::from),
+            "j7".parse().ok()
+        );
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        assert_eq!(coord_from_xy(-1, 0), None);
+        assert_eq!(coord_from_xy(0, -1), None);
+        assert_eq!(coord_from_xy(10, 0), None);
+        assert_eq!(coord_from_xy(0, 10), None);
+    }
+}
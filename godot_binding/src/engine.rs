@@ -0,0 +1,183 @@
+use crate::{array_from_coord, coord_from_array};
+use amazons_core::{minimax, Board, Move, SearchLimits, TranspositionTable, EVALUATORS};
+use godot::classes::{INode, Node};
+use godot::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// What the worker thread sends back: which search it was, the move it found, and the
+/// transposition table so it can be reused for the next search.
+type SearchResult = (u64, Option<Move>, TranspositionTable);
+
+/// An AI that runs in the same process as the UI. It keeps its own copy of the board, which must
+/// be told about the opponent's moves with `notify_of_move`. Searching happens on a worker
+/// thread, and `move_ready` is emitted once the move has been chosen, or `engine_error` if the
+/// search didn't find one.
+#[derive(GodotClass)]
+#[class(base=Node)]
+pub struct AmazonsEngine {
+    base: Base<Node>,
+
+    /// How long to think about each move, in milliseconds
+    #[export]
+    move_time_ms: i64,
+
+    board: Board,
+    // `None` while a search is using it
+    tt: Option<TranspositionTable>,
+    // Every search sends its result here, including abandoned ones, which still hand back the
+    // table
+    results: Receiver<SearchResult>,
+    sender: Sender<SearchResult>,
+    // The thread running the current search, `None` once it has been abandoned
+    worker: Option<JoinHandle<()>>,
+    // Tells the worker thread to give up on the search it is running
+    stop: Option<Arc<AtomicBool>>,
+    // Incremented every time a search starts or the game is reset, so stale results are ignored
+    search_id: u64,
+}
+
+#[godot_api]
+impl INode for AmazonsEngine {
+    fn init(base: Base<Node>) -> Self {
+        let (sender, results) = channel();
+        Self {
+            base,
+            move_time_ms: 10_000,
+            board: Board::default(),
+            tt: None,
+            results,
+            sender,
+            worker: None,
+            stop: None,
+            search_id: 0,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        while let Ok((search_id, mov, tt)) = self.results.try_recv() {
+            // An abandoned search may return its table after the next search started with a new
+            // one, in which case the latest is kept
+            self.tt = Some(tt);
+            if search_id != self.search_id {
+                continue;
+            }
+            self.worker = None;
+            self.stop = None;
+            if let Some(mov) = mov {
+                self.board.apply_move(&mov);
+                let Move(piece, mov, arrow) = mov;
+                let args = [
+                    array_from_coord(piece).to_variant(),
+                    array_from_coord(mov).to_variant(),
+                    array_from_coord(arrow).to_variant(),
+                ];
+                self.base_mut().emit_signal("move_ready", &args);
+            } else {
+                self.report_error("Engine didn't find a move".to_string());
+            }
+        }
+        // The result is sent before the thread finishes, so if it has finished without one
+        // arriving above, it panicked
+        if self.worker.as_ref().is_some_and(JoinHandle::is_finished) {
+            self.worker = None;
+            self.stop = None;
+            self.report_error("Engine search thread stopped without finding a move".to_string());
+        }
+    }
+
+    fn exit_tree(&mut self) {
+        self.abandon_search();
+    }
+}
+
+#[godot_api]
+impl AmazonsEngine {
+    #[signal]
+    fn move_ready(piece: Array<i64>, mov: Array<i64>, arrow: Array<i64>);
+
+    /// Emitted when a search ends without a move, which shouldn't happen while the game is on.
+    #[signal]
+    fn engine_error(message: GString);
+
+    /// Resets the engine's board to the starting position, discarding any search in progress.
+    #[func]
+    fn new_game(&mut self) {
+        self.board = Board::default();
+        self.abandon_search();
+    }
+
+    /// Applies a move made by the opponent, discarding any search in progress. Returns false if
+    /// the move is not legal.
+    #[func]
+    fn notify_of_move(&mut self, piece: Array<i64>, mov: Array<i64>, arrow: Array<i64>) -> bool {
+        self.abandon_search();
+        let (Some(piece), Some(mov), Some(arrow)) = (
+            coord_from_array(&piece),
+            coord_from_array(&mov),
+            coord_from_array(&arrow),
+        ) else {
+            godot_error!("Engine was notified of a move that is off the board");
+            return false;
+        };
+        match self.board.try_apply_move(&Move(piece, mov, arrow)) {
+            Ok(()) => true,
+            Err(err) => {
+                godot_error!("Engine was notified of an illegal move: {err}");
+                false
+            }
+        }
+    }
+
+    /// Starts searching for a move for the player to move. `move_ready` is emitted when it is
+    /// found. Any search that is already running is abandoned without waiting for it, and if it
+    /// hasn't handed back the transposition table yet the new search starts with an empty one.
+    #[func]
+    fn start_thinking(&mut self) {
+        self.abandon_search();
+        let search_id = self.search_id;
+        let board = self.board.clone();
+        let tt = self.tt.take();
+        let stop = Arc::new(AtomicBool::new(false));
+        self.stop = Some(stop.clone());
+        let limits = SearchLimits {
+            move_time: Some(Duration::from_millis(self.move_time_ms.max(0) as u64)),
+            stop: Some(stop),
+            ..Default::default()
+        };
+        let sender = self.sender.clone();
+        self.worker = Some(thread::spawn(move || {
+            let mut tt = tt.unwrap_or_else(|| TranspositionTable::with_size_mb(64));
+            let (mov, _) = minimax(&board, &mut tt, &limits, EVALUATORS[0]);
+            // If the engine was freed in the meantime there is nobody to tell
+            let _ = sender.send((search_id, mov, tt));
+        }));
+    }
+
+    /// Whether a search is running that will emit `move_ready`. Abandoned searches don't count.
+    #[func]
+    fn is_thinking(&self) -> bool {
+        self.worker.is_some()
+    }
+}
+
+impl AmazonsEngine {
+    /// Stops any search in progress and makes sure its result is ignored. Its transposition table
+    /// is picked up in `process` once it hands it back.
+    fn abandon_search(&mut self) {
+        self.search_id += 1;
+        self.worker = None;
+        if let Some(stop) = self.stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn report_error(&mut self, message: String) {
+        godot_error!("{message}");
+        self.base_mut()
+            .emit_signal("engine_error", &[GString::from(message).to_variant()]);
+    }
+}
@@ -1,140 +1,312 @@
-use amazons_core::{Coord, Dim, Move};
+use amazons_core::process::{EngineProcess, ProcessError};
+use amazons_core::protocol::{Command, Go, Response};
+use amazons_core::{Board, Coord, Move};
+use coords::{coord_from_xy, xy_from_coord};
 use godot::classes::{INode, Node, Os};
 use godot::prelude::*;
-use std::io::{BufRead, BufReader, Write};
-use std::process::{ChildStdin, ChildStdout, Command, Stdio};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+mod board;
+mod coords;
+mod engine;
 
 struct MyExtension;
 
 #[gdextension]
 unsafe impl ExtensionLibrary for MyExtension {}
 
+/// How many lines of the child's output to keep for `get_log`.
+const MAX_LOG_LINES: usize = 1000;
+
+/// Talks to an AI running as a child process using the protocol in `amazons_core::protocol`. The
+/// child is sent the game so far and asked for a move by `expect_move`. Its output is read on a
+/// background thread, and `move_ready` is emitted when it replies. The child is killed when this
+/// node leaves the tree.
 #[derive(GodotClass)]
 #[class(base=Node)]
 struct CliInterface {
-    #[base]
-    _base: Base<Node>,
+    base: Base<Node>,
 
-    child_io: Option<(ChildStdin, BufReader<ChildStdout>)>,
+    /// How long the child may take to reply after `expect_move`, in milliseconds. It is killed if
+    /// it takes longer. Zero means there is no limit.
+    #[export]
+    move_timeout_ms: i64,
+
+    // The program and arguments to launch, so the child can be restarted
+    command: Vec<String>,
+    // Every move played in the game so far, by both sides
+    moves: Vec<Move>,
+    process: Option<EngineProcess>,
+    // The most recent lines the child wrote to stderr, and any protocol messages it sent that
+    // weren't moves
+    log: VecDeque<String>,
+    move_requested_at: Option<Instant>,
 }
 
 #[godot_api]
 impl INode for CliInterface {
     fn init(base: Base<Node>) -> Self {
         Self {
-            _base: base,
-            child_io: None,
+            base,
+            move_timeout_ms: 30_000,
+            command: vec![],
+            moves: vec![],
+            process: None,
+            log: VecDeque::new(),
+            move_requested_at: None,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        if self.process.is_none() {
+            return;
+        }
+        self.read_log();
+        match self.poll_move() {
+            Ok(Some(Move(piece, mov, arrow))) => {
+                self.move_requested_at = None;
+                let args = [
+                    array_from_coord(piece).to_variant(),
+                    array_from_coord(mov).to_variant(),
+                    array_from_coord(arrow).to_variant(),
+                ];
+                self.base_mut().emit_signal("move_ready", &args);
+            }
+            Ok(None) => self.check_timeout(),
+            Err(message) => self.report_error(message),
         }
     }
+
+    fn exit_tree(&mut self) {
+        self.stop();
+    }
 }
 
 #[godot_api]
 impl CliInterface {
+    #[signal]
+    fn move_ready(piece: Array<i64>, mov: Array<i64>, arrow: Array<i64>);
+
+    /// Emitted when the child process misbehaves, e.g. by sending something that isn't a move,
+    /// crashing or taking too long to move.
+    #[signal]
+    fn engine_error(message: GString);
+
+    /// Emitted for every line added to the log, see `get_log`.
+    #[signal]
+    fn log_line(line: GString);
+
     #[func]
     fn start_black(&mut self) -> bool {
-        let args: Vec<String> = Os::singleton()
-            .get_cmdline_user_args()
-            .to_vec()
-            .iter()
-            .map(|gs| gs.into())
-            .collect();
-        let black_args: Vec<&str> = args
-            .iter()
-            .skip_while(|s| s != &"--black")
-            .skip(1)
-            .take_while(|s| s != &"--white")
-            .map(String::as_ref)
-            .collect();
-        if !black_args.is_empty() {
-            let child = Command::new(black_args[0])
-                .args(black_args.iter().skip(1))
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("Failed to start black player");
-            self.child_io = Some((child.stdin.unwrap(), BufReader::new(child.stdout.unwrap())));
-            true
-        } else {
-            false
-        }
+        self.command = command_from_args("--black", "--white");
+        self.restart()
     }
 
     #[func]
     fn start_white(&mut self) -> bool {
-        let args: Vec<String> = Os::singleton()
-            .get_cmdline_user_args()
-            .to_vec()
-            .iter()
-            .map(|gs| gs.into())
-            .collect();
-        let white_args: Vec<&str> = args
-            .iter()
-            .skip_while(|s| s != &"--white")
-            .skip(1)
-            .take_while(|s| s != &"--black")
-            .map(String::as_ref)
-            .collect();
-        if !white_args.is_empty() {
-            let child = Command::new(white_args[0])
-                .args(white_args.iter().skip(1))
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("Failed to start black player");
-            self.child_io = Some((child.stdin.unwrap(), BufReader::new(child.stdout.unwrap())));
-            true
-        } else {
-            false
+        self.command = command_from_args("--white", "--black");
+        self.restart()
+    }
+
+    /// Kills the child, if it's running, and launches it again with the same command. The new
+    /// child starts from a new game. Returns false if there's no command or it couldn't be
+    /// started.
+    #[func]
+    fn restart(&mut self) -> bool {
+        self.stop();
+        self.moves.clear();
+        let Some((program, args)) = self.command.split_first() else {
+            return false;
+        };
+        match EngineProcess::spawn(program, args) {
+            Ok(process) => {
+                self.push_log(format!("[started {}]", self.command.join(" ")));
+                self.process = Some(process);
+                self.send(Command::Amazons);
+                self.send(Command::NewGame);
+                true
+            }
+            Err(err) => {
+                let message = format!("Failed to start {program}: {err}");
+                self.report_error(message);
+                false
+            }
+        }
+    }
+
+    /// Kills the child, if it's running.
+    #[func]
+    fn stop(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            process.kill();
+            self.push_log("[stopped]".to_string());
         }
+        self.move_requested_at = None;
     }
 
+    /// Whether a command was given for this player, even if the child isn't running right now.
     #[func]
     fn is_enabled(&self) -> bool {
-        self.child_io.is_some()
+        !self.command.is_empty()
+    }
+
+    #[func]
+    fn is_running(&self) -> bool {
+        self.process.is_some()
     }
 
+    /// Records a move that was played on the board, by either side. The child's own moves are
+    /// only recorded this way too, so a move the scene rejected is never sent back to it.
     #[func]
     fn notify_of_move(&mut self, piece: Array<i64>, mov: Array<i64>, arrow: Array<i64>) {
-        let piece = Coord(
-            Dim::from(usize::try_from(piece.get(0).unwrap()).unwrap()),
-            Dim::from(usize::try_from(piece.get(1).unwrap()).unwrap()),
-        );
-        let mov = Coord(
-            Dim::from(usize::try_from(mov.get(0).unwrap()).unwrap()),
-            Dim::from(usize::try_from(mov.get(1).unwrap()).unwrap()),
-        );
-        let arrow = Coord(
-            Dim::from(usize::try_from(arrow.get(0).unwrap()).unwrap()),
-            Dim::from(usize::try_from(arrow.get(1).unwrap()).unwrap()),
-        );
-        let move_string = format!("{}\n", Move::notation_for(&piece, &mov, &arrow));
-        println!("Notifying CLI of {move_string}");
-        if let Some((child_stdin, _)) = &mut self.child_io {
-            child_stdin
-                .write_all(move_string.as_bytes())
-                .expect("IO error notifying CLI of move");
-        }
+        let (Some(piece), Some(mov), Some(arrow)) = (
+            coord_from_array(&piece),
+            coord_from_array(&mov),
+            coord_from_array(&arrow),
+        ) else {
+            godot_error!("CLI was notified of a move that is off the board");
+            return;
+        };
+        // The child is sent the whole game when it's asked to move
+        self.moves.push(Move(piece, mov, arrow));
     }
 
+    /// Asks the child for a move in the current position, and starts the clock on it, see
+    /// `move_timeout_ms`.
     #[func]
-    fn get_move(&mut self) -> VariantArray {
-        if let Some((_, child_stdout)) = &mut self.child_io {
-            let mut notation = String::new();
-            if child_stdout.read_line(&mut notation).is_ok() {
-                println!("CLI plays {notation}");
-                if let Some(Move(piece, mov, arrow)) = Move::parse_notation(&notation) {
-                    return varray![
-                        array![usize::from(&piece.0) as i64, usize::from(&piece.1) as i64],
-                        array![usize::from(&mov.0) as i64, usize::from(&mov.1) as i64],
-                        array![usize::from(&arrow.0) as i64, usize::from(&arrow.1) as i64]
-                    ];
+    fn expect_move(&mut self) {
+        self.send(Command::Position {
+            start: Board::default(),
+            moves: self.moves.clone(),
+        });
+        self.send(Command::Go(Go::default()));
+        self.move_requested_at = Some(Instant::now());
+    }
+
+    /// The most recent lines the child wrote to stderr and the messages it sent other than moves,
+    /// along with notes about when it was started and stopped.
+    #[func]
+    fn get_log(&self) -> GString {
+        let lines: Vec<&str> = self.log.iter().map(String::as_str).collect();
+        GString::from(lines.join("\n"))
+    }
+}
+
+impl CliInterface {
+    fn send(&mut self, command: Command) {
+        let Some(process) = &mut self.process else {
+            return;
+        };
+        let line = command.to_string();
+        if let Err(err) = process.send_line(&line) {
+            self.report_error(format!("Failed to send {line:?} to CLI: {err}"));
+        }
+    }
+
+    /// Checks whether the child has sent a move, without waiting for one.
+    fn poll_move(&mut self) -> Result<Option<Move>, String> {
+        loop {
+            let Some(process) = &mut self.process else {
+                return Ok(None);
+            };
+            let line = match process.try_read_line() {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => line,
+                Ok(None) => return Ok(None),
+                Err(ProcessError::Exited(status)) if status.success() => {
+                    self.process = None;
+                    self.push_log(format!("[exited with {status}]"));
+                    // Exiting is fine between moves, but not while we're waiting for one
+                    if self.move_requested_at.take().is_some() {
+                        return Err("CLI engine exited before moving".to_string());
+                    }
+                    return Ok(None);
+                }
+                Err(err) => {
+                    self.process = None;
+                    self.push_log(format!("[crashed: {err}]"));
+                    return Err(format!("CLI crashed: {err}"));
+                }
+            };
+            match line.parse() {
+                Ok(Response::BestMove(Some(mov))) => {
+                    // It's added to `moves` by `notify_of_move` once the scene has accepted it
+                    return Ok(Some(mov));
+                }
+                Ok(Response::BestMove(None)) => {
+                    self.move_requested_at = None;
+                    self.push_log("[has no moves left]".to_string());
+                    return Ok(None);
                 }
-            } else {
-                panic!("Failed to read_line")
+                // Nothing else needs a reply, but it's useful to see
+                Ok(_) => self.push_log(line),
+                Err(err) => return Err(format!("CLI sent {line:?}: {err}")),
             }
-        } else {
-            println!("Failed to unlock child_stdout");
         }
-        panic!("Failed to get a move from the CLI")
     }
+
+    fn check_timeout(&mut self) {
+        let Some(requested_at) = self.move_requested_at else {
+            return;
+        };
+        let timeout = Duration::from_millis(self.move_timeout_ms.max(0) as u64);
+        if !timeout.is_zero() && requested_at.elapsed() > timeout {
+            self.stop();
+            self.report_error(format!(
+                "CLI took longer than {}ms to move and was stopped",
+                self.move_timeout_ms
+            ));
+        }
+    }
+
+    fn read_log(&mut self) {
+        let Some(process) = &mut self.process else {
+            return;
+        };
+        for line in process.stderr_lines() {
+            self.push_log(line);
+        }
+    }
+
+    fn push_log(&mut self, line: String) {
+        if self.log.len() == MAX_LOG_LINES {
+            self.log.pop_front();
+        }
+        self.base_mut()
+            .emit_signal("log_line", &[GString::from(line.as_str()).to_variant()]);
+        self.log.push_back(line);
+    }
+
+    fn report_error(&mut self, message: String) {
+        godot_error!("{message}");
+        self.base_mut()
+            .emit_signal("engine_error", &[GString::from(message).to_variant()]);
+    }
+}
+
+/// The program and arguments following `flag` on the command line, up to `other_flag`.
+fn command_from_args(flag: &str, other_flag: &str) -> Vec<String> {
+    let args: Vec<String> = Os::singleton()
+        .get_cmdline_user_args()
+        .to_vec()
+        .iter()
+        .map(|gs| gs.into())
+        .collect();
+    args.into_iter()
+        .skip_while(|s| s != flag)
+        .skip(1)
+        .take_while(|s| s != other_flag)
+        .collect()
+}
+
+/// Converts an `[x, y]` array from the scene into a core coordinate.
+fn coord_from_array(array: &Array<i64>) -> Option<Coord> {
+    coord_from_xy(array.get(0)?, array.get(1)?)
+}
+
+/// Converts a core coordinate into an `[x, y]` array for the scene.
+fn array_from_coord(coord: Coord) -> Array<i64> {
+    let (x, y) = xy_from_coord(coord);
+    array![x, y]
 }